@@ -1,117 +1,350 @@
-use crate::logo::turtle::Command;
+use crate::logo::turtle::{Command, Record, Shape};
+use std::collections::BTreeMap;
 use std::{f32::consts::PI, f64};
 use web_sys::CanvasRenderingContext2d;
-pub struct CanvasPlotter<'a> {
-    context: &'a CanvasRenderingContext2d,
-    current_heading: f32,
+
+/// What the plotter remembers about each turtle between commands.
+struct Head {
+    pos: (f32, f32),
+    heading: f32,
+    visible: bool,
+    shape: Shape,
+    color: Option<(u8, u8, u8)>,
+    pensize: f32,
+}
+
+impl Default for Head {
+    fn default() -> Self {
+        Self {
+            pos: (0.0, 0.0),
+            heading: 0.0,
+            visible: true,
+            shape: Shape::Triangle,
+            color: None,
+            pensize: 1.0,
+        }
+    }
+}
+
+pub struct CanvasPlotter {
+    context: CanvasRenderingContext2d,
     size: (u32, u32),
-    current_pos: (f32, f32),
+    heads: BTreeMap<usize, Head>,
+    current: usize,
+    default_style: String,
+    zoom: f32,
 }
 
-impl<'a> CanvasPlotter<'a> {
+impl CanvasPlotter {
     /// Creates a new [`CanvasPlotter`].
-    pub fn new(context: &'a CanvasRenderingContext2d, size: (u32, u32)) -> Self {
+    pub fn new(context: CanvasRenderingContext2d, size: (u32, u32), zoom: f32) -> Self {
+        let default_style = context
+            .stroke_style()
+            .as_string()
+            .unwrap_or_else(|| "#000000".into());
         Self {
             context,
             size,
-            current_heading: 0.0,
-            current_pos: (0.0, 0.0),
+            heads: BTreeMap::new(),
+            current: 0,
+            default_style,
+            zoom,
+        }
+    }
+
+    pub fn plot(&mut self, records: &[Record]) {
+        self.reset();
+        for record in records {
+            self.apply(record);
         }
+        self.draw_overlay();
     }
-    pub fn plot(&mut self, commands: &[Command]) {
+
+    /// Clears the canvas and forgets about all turtles
+    pub fn reset(&mut self) {
+        self.heads.clear();
+        self.current = 0;
+        self.context.set_stroke_style_str(&self.default_style);
         self.clear();
+    }
 
-        for c in commands {
-            match c {
-                Command::Line((sx, sy), (x, y)) => {
-                    self.context.begin_path();
-                    self.context.move_to(f64::from(*sx), f64::from(*sy));
-                    self.context.line_to(f64::from(*x), f64::from(*y));
-                    self.context.stroke();
-                    self.current_pos = (*x, *y);
-                }
-                Command::Move(_, (x, y)) => {
-                    self.current_pos = (*x, *y);
-                }
-                Command::Heading(h) => self.current_heading = *h,
-                Command::Color(c) => {
+    /// Draws a single record onto the canvas
+    pub fn apply(&mut self, record: &Record) {
+        let Record {
+            turtle, command, ..
+        } = record;
+        if *turtle != self.current {
+            self.select(*turtle);
+        }
+        let head = self.heads.entry(*turtle).or_default();
+        match command {
+            Command::Line((sx, sy), (x, y)) => {
+                self.context.begin_path();
+                self.context.move_to(f64::from(*sx), f64::from(*sy));
+                self.context.line_to(f64::from(*x), f64::from(*y));
+                self.context.stroke();
+                head.pos = (*x, *y);
+            }
+            Command::Move(_, (x, y)) => {
+                head.pos = (*x, *y);
+            }
+            Command::Heading(h) => head.heading = *h,
+            Command::Color(c) => {
+                head.color = Some(*c);
+                self.context
+                    .set_stroke_style_str(&format!("rgba({},{},{})", c.0, c.1, c.2));
+            }
+            Command::Clear => {
+                self.clear();
+            }
+            Command::Circle(rad) => {
+                self.context.begin_path();
+                let _res = self.context.arc(
+                    f64::from(head.pos.0),
+                    f64::from(head.pos.1),
+                    f64::from(*rad),
+                    0.0f64,
+                    2.0 * f64::from(PI),
+                );
+                self.context.stroke();
+            }
+            Command::Arc(angle, rad) => {
+                self.context.begin_path();
+                let _res = self.context.arc(
+                    f64::from(head.pos.0),
+                    f64::from(head.pos.1),
+                    f64::from(*rad),
+                    f64::from((-90.0 + head.heading).to_radians()),
+                    f64::from(angle.to_radians()),
+                );
+                self.context.stroke();
+            }
+            Command::Pensize(w) => {
+                head.pensize = *w;
+                self.context.set_line_width(f64::from(*w));
+            }
+            Command::Visible(v) => head.visible = *v,
+            Command::Shape(shape) => head.shape = shape.clone(),
+            Command::Speed(_) | Command::Wait(_) => {}
+            Command::Label(text) => {
+                self.context.save();
+                if let Some(c) = head.color {
                     self.context
-                        .set_stroke_style(&format!("rgba({},{},{})", c.0, c.1, c.2).into());
-                }
-                Command::Clear => {
-                    self.clear();
+                        .set_fill_style_str(&format!("rgba({},{},{})", c.0, c.1, c.2));
                 }
-                Command::Circle(rad) => {
-                    self.context.begin_path();
-                    let _res = self.context.arc(
-                        f64::from(self.current_pos.0),
-                        f64::from(self.current_pos.1),
-                        f64::from(*rad),
-                        0.0f64,
-                        2.0 * f64::from(PI),
-                    );
-                    self.context.stroke();
-                }
-                Command::Arc(angle, rad) => {
-                    self.context.begin_path();
-                    let _res = self.context.arc(
-                        f64::from(self.current_pos.0),
-                        f64::from(self.current_pos.1),
-                        f64::from(*rad),
-                        f64::from((-90.0 + self.current_heading).to_radians()),
-                        f64::from(angle.to_radians()),
-                    );
-                    self.context.stroke();
+                let _res = self
+                    .context
+                    .translate(f64::from(head.pos.0), f64::from(head.pos.1));
+                let _res = self.context.rotate(f64::from(label_rotation(head.heading)));
+                let _res = self.context.fill_text(text, 0.0, 0.0);
+                self.context.restore();
+            }
+            Command::Stamp {
+                shape,
+                position,
+                heading,
+                color,
+            } => {
+                self.context.save();
+                if let Some(c) = color {
+                    self.context
+                        .set_fill_style_str(&format!("rgba({},{},{})", c.0, c.1, c.2));
                 }
-                Command::Pensize(w) => self.context.set_line_width(f64::from(*w)),
+                self.draw_head(shape, *position, (-90.0 + heading).to_radians());
+                self.context.restore();
             }
         }
+    }
 
+    /// Draws the part of a line, arc or circle between the fractions `from`
+    /// and `to` of its length, moving the head along. Anything else is drawn
+    /// whole.
+    pub fn apply_partial(&mut self, record: &Record, from: f32, to: f32) {
+        let Record {
+            turtle, command, ..
+        } = record;
+        if *turtle != self.current {
+            self.select(*turtle);
+        }
+        let head = self.heads.entry(*turtle).or_default();
+        match command {
+            Command::Line((sx, sy), (x, y)) => {
+                let lerp = |t: f32| ((x - sx).mul_add(t, *sx), (y - sy).mul_add(t, *sy));
+                let (start, end) = (lerp(from), lerp(to));
+                self.context.begin_path();
+                self.context.move_to(f64::from(start.0), f64::from(start.1));
+                self.context.line_to(f64::from(end.0), f64::from(end.1));
+                self.context.stroke();
+                head.pos = end;
+            }
+            Command::Circle(rad) => {
+                let sweep = 2.0 * PI;
+                self.context.begin_path();
+                let _res = self.context.arc(
+                    f64::from(head.pos.0),
+                    f64::from(head.pos.1),
+                    f64::from(*rad),
+                    f64::from(sweep * from),
+                    f64::from(sweep * to),
+                );
+                self.context.stroke();
+            }
+            Command::Arc(angle, rad) => {
+                // the canvas sweeps clockwise from the heading to `angle`
+                let start = (-90.0 + head.heading).to_radians();
+                let sweep = (angle.to_radians() - start).rem_euclid(2.0 * PI);
+                self.context.begin_path();
+                let _res = self.context.arc(
+                    f64::from(head.pos.0),
+                    f64::from(head.pos.1),
+                    f64::from(*rad),
+                    f64::from(sweep.mul_add(from, start)),
+                    f64::from(sweep.mul_add(to, start)),
+                );
+                self.context.stroke();
+            }
+            _ => self.apply(record),
+        }
+    }
+
+    /// Draws what isn't part of the drawing: the out of bounds frame and the
+    /// turtle heads
+    pub fn draw_overlay(&self) {
+        self.context.save();
         #[allow(clippy::cast_precision_loss)]
-        if self.current_pos.0 < 0.0
-            || self.current_pos.1 < 0.0
-            || self.current_pos.0 > self.size.0 as f32
-            || self.current_pos.1 > self.size.1 as f32
-        {
+        if self.heads.values().any(|head| {
+            head.pos.0 < 0.0
+                || head.pos.1 < 0.0
+                || head.pos.0 > self.size.0 as f32
+                || head.pos.1 > self.size.1 as f32
+        }) {
             self.context.set_line_width(4.0);
             self.context
                 .stroke_rect(0.0, 0.0, f64::from(self.size.0), f64::from(self.size.1));
             //for white background
         }
 
-        self.draw_head(
-            self.current_pos.0,
-            self.current_pos.1,
-            (-90.0 + self.current_heading).to_radians(),
-        );
+        for head in self.heads.values().filter(|head| head.visible) {
+            self.draw_head(&head.shape, head.pos, (-90.0 + head.heading).to_radians());
+        }
+        self.context.restore();
+    }
+
+    /// Restores the pen of turtle `id` on the context, since turtles share it
+    fn select(&mut self, id: usize) {
+        self.current = id;
+        let head = self.heads.entry(id).or_default();
+        match head.color {
+            Some(c) => self
+                .context
+                .set_stroke_style_str(&format!("rgba({},{},{})", c.0, c.1, c.2)),
+            None => self.context.set_stroke_style_str(&self.default_style),
+        }
+        self.context.set_line_width(f64::from(head.pensize));
     }
 
-    fn clear(&self) {
+    fn clear(&mut self) {
+        for head in self.heads.values_mut() {
+            head.pensize = 1.0;
+        }
         self.context.set_line_width(1.0);
         self.context
             .clear_rect(0.0, 0.0, f64::from(self.size.0), f64::from(self.size.1));
     }
 
-    fn draw_head(&self, x: f32, y: f32, angle: f32) {
-        let size = 9.0f32;
-        let span2 = angle - 2.3;
-        let span4 = angle + 2.3;
-
-        // Draw the triangle
+    fn draw_head(&self, shape: &Shape, (x, y): (f32, f32), angle: f32) {
         self.context.begin_path();
-        self.context.line_to(
-            f64::from(size.mul_add(angle.cos(), x)),
-            f64::from(size.mul_add(angle.sin(), y)),
-        );
-        self.context.line_to(
-            f64::from(size.mul_add(span2.cos(), x)),
-            f64::from(size.mul_add(span2.sin(), y)),
-        );
-        self.context.line_to(
-            f64::from(size.mul_add(span4.cos(), x)),
-            f64::from(size.mul_add(span4.sin(), y)),
-        );
+        if let Shape::Circle = shape {
+            let _res = self.context.arc(
+                f64::from(x),
+                f64::from(y),
+                f64::from(6.0 * self.zoom),
+                0.0f64,
+                2.0 * f64::from(PI),
+            );
+        } else {
+            // turtle-local points: `fwd` is along the heading, `side` to its right
+            let (sin, cos) = angle.sin_cos();
+            for (side, fwd) in outline(shape) {
+                let (side, fwd) = (side * self.zoom, fwd * self.zoom);
+                self.context.line_to(
+                    f64::from(fwd.mul_add(cos, side.mul_add(-sin, x))),
+                    f64::from(fwd.mul_add(sin, side.mul_add(cos, y))),
+                );
+            }
+        }
         self.context.close_path();
         self.context.fill();
     }
 }
+
+/// How far a label is turned, in radians clockwise, for a turtle facing
+/// `heading`: the text turns with the turtle, and reads upright left to right
+/// at heading 0
+fn label_rotation(heading: f32) -> f32 {
+    heading.to_radians()
+}
+
+fn outline(shape: &Shape) -> Vec<(f32, f32)> {
+    match shape {
+        Shape::Triangle => {
+            // the classic head: a 9px isosceles triangle with 2.3 rad wings
+            let size = 9.0f32;
+            let (sin, cos) = 2.3f32.sin_cos();
+            vec![
+                (0.0, size),
+                (-size * sin, size * cos),
+                (size * sin, size * cos),
+            ]
+        }
+        Shape::Arrow => vec![
+            (0.0, 10.0),
+            (-7.0, 2.0),
+            (-3.0, 2.0),
+            (-3.0, -8.0),
+            (3.0, -8.0),
+            (3.0, 2.0),
+            (7.0, 2.0),
+        ],
+        Shape::Turtle => vec![
+            (0.0, 12.0),
+            (2.0, 10.0),
+            (2.0, 8.0),
+            (5.0, 7.0),
+            (8.0, 9.0),
+            (9.0, 7.0),
+            (6.0, 4.0),
+            (6.0, -2.0),
+            (8.0, -5.0),
+            (7.0, -7.0),
+            (4.0, -5.0),
+            (1.0, -7.0),
+            (0.0, -9.0),
+            (-1.0, -7.0),
+            (-4.0, -5.0),
+            (-7.0, -7.0),
+            (-8.0, -5.0),
+            (-6.0, -2.0),
+            (-6.0, 4.0),
+            (-9.0, 7.0),
+            (-8.0, 9.0),
+            (-5.0, 7.0),
+            (-2.0, 8.0),
+            (-2.0, 10.0),
+        ],
+        Shape::Polygon(points) => points.clone(),
+        Shape::Circle => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_upright_at_heading_zero_and_turn_with_the_turtle() {
+        assert!(label_rotation(0.0).abs() < f32::EPSILON);
+        assert!((label_rotation(90.0) - PI / 2.0).abs() < f32::EPSILON);
+        assert!((label_rotation(180.0) - PI).abs() < f32::EPSILON);
+    }
+}
@@ -1,8 +1,17 @@
 #![allow(clippy::missing_const_for_fn)]
 mod canvas_plotter;
 mod logo;
+mod player;
+mod session;
 use canvas_plotter::CanvasPlotter;
 use logo::executor::Executor;
+use logo::formatter::{self, Aliases};
+use logo::locale::Locale;
+use logo::profiler::Profile;
+use logo::service;
+use logo::turtle::{Origin, Record};
+use logo::{Diagnostic, LogoError};
+use serde::Serialize;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 
@@ -16,7 +25,17 @@ pub struct Opts {
     pub canvas_id: String,
     pub x: f32,
     pub y: f32,
+    /// How many VM instructions a program may run, and how many commands it
+    /// may draw, before it fails as running too long. Every instruction
+    /// counts, not just the commands drawn, so loops that draw nothing end
+    /// too. Defaults to a million, enough for the fern example.
     pub budget: usize,
+    pub zoom: f32,
+    pub speed: f32,
+    pub trace: bool,
+    pub profile: bool,
+    pub locale: String,
+    pub abbreviate: bool,
 }
 
 #[wasm_bindgen]
@@ -29,28 +48,214 @@ impl Opts {
             canvas_id: String::new(),
             x: 0.0,
             y: 0.0,
-            budget: 130_000,
+            budget: 1_000_000,
+            zoom: 1.0,
+            speed: logo::turtle::DEFAULT_SPEED,
+            trace: false,
+            profile: false,
+            locale: String::from("logo"),
+            abbreviate: false,
         }
     }
 }
 
-/// run and spit out IR commands
+impl Opts {
+    /// the language to read code and write errors in, besides English
+    fn locale(&self) -> &'static Locale {
+        Locale::find(&self.locale)
+    }
+
+    /// whether `format` spells keywords `FD` rather than `FORWARD`
+    fn aliases(&self) -> Aliases {
+        if self.abbreviate {
+            Aliases::Contract
+        } else {
+            Aliases::Expand
+        }
+    }
+}
+
+/// hands a value to JS as its JSON would read: maps as plain objects and
+/// `None` as `null`
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|err| format!("error: {err:?}").into())
+}
+
+/// what `run` hands back: the drawing, what the program printed, and
+/// warnings about the code. With `opts.trace`, also where each command came
+/// from, index for index, and with `opts.profile`, where the work was done.
+#[derive(Serialize)]
+struct RunResult {
+    commands: Vec<Record>,
+    trace: Option<Vec<Origin>>,
+    profile: Option<Profile>,
+    output: Vec<String>,
+    warnings: Vec<Diagnostic>,
+}
+
+/// what `run` and `draw` throw when the program fails: the error, and the
+/// warnings about the code, which often explain it
+#[derive(Serialize)]
+struct RunError {
+    message: String,
+    warnings: Vec<Diagnostic>,
+}
+
+/// `err` as `run` and `draw` throw it, with the warnings `exec` found
+fn run_error(opts: &Opts, exec: &Executor, err: &LogoError) -> JsValue {
+    to_js(&RunError {
+        message: opts.locale().message(err),
+        warnings: exec.warnings(),
+    })
+    .unwrap_or_else(|err| err)
+}
+
+/// run and spit out IR commands, along with printed lines
 ///
+/// # Errors
+///
+/// This function will return an error if the program fails to parse or run,
+/// as `{message, warnings}`.
 #[wasm_bindgen]
 pub fn run(opts: &Opts, code: &str) -> Result<JsValue, JsValue> {
     let mut exec = Executor::new();
+    exec.set_locale(opts.locale());
+    exec.start_lint();
     let mut tt = logo::turtle::Turtle::new((opts.x, opts.y), opts.budget);
+    tt.set_speed(opts.speed);
+    tt.canvas = canvas_size(&opts.canvas_id);
+    if opts.trace {
+        tt.start_trace();
+    }
+    if opts.profile {
+        exec.start_profile();
+    }
     match exec.run(&mut tt, code) {
         Ok(_) => {
             let cmds = tt.commands();
-            JsValue::from_serde(&cmds).map_err(|err| format!("error: {err:?}").into())
+            let res = RunResult {
+                commands: cmds,
+                trace: tt.trace.take(),
+                profile: exec.profile(),
+                output: exec.output(),
+                warnings: exec.warnings(),
+            };
+            to_js(&res)
             // console::log_1(&format!("{cmds:?}").into());
         }
-        Err(err) => Err(format!("{err:?}").into()),
+        Err(err) => Err(run_error(opts, &exec, &err)),
     }
 }
 
-/// draw direct to canvas
+/// format code canonically: a statement a line, uppercase keywords and
+/// indented blocks, keeping comments
+///
+/// # Errors
+///
+/// This function will return an error if the code doesn't parse.
+#[wasm_bindgen]
+pub fn format(opts: &Opts, code: &str) -> Result<String, JsValue> {
+    format_code(opts, code).map_err(JsValue::from)
+}
+
+/// format code as `format` does, with the error as a plain message, for
+/// callers outside JS such as the `dom-logo format` command
+///
+/// # Errors
+///
+/// This function will return an error if the code doesn't parse.
+pub fn format_code(opts: &Opts, code: &str) -> Result<String, String> {
+    formatter::format(code, opts.locale(), opts.aliases())
+        .map_err(|err| opts.locale().message(&err))
+}
+
+/// run code with the profiler on, and return where it spent its budget as
+/// JSON, as `run` has it with `opts.profile`, for callers outside JS such as
+/// the `dom-logo profile` command
+///
+/// # Errors
+///
+/// This function will return an error if the program fails to parse or run.
+pub fn profile_code(opts: &Opts, code: &str) -> Result<String, String> {
+    let mut exec = Executor::new();
+    exec.set_locale(opts.locale());
+    exec.start_profile();
+    let mut tt = logo::turtle::Turtle::new((opts.x, opts.y), opts.budget);
+    tt.set_speed(opts.speed);
+    exec.run(&mut tt, code)
+        .map_err(|err| opts.locale().message(&err))?;
+    serde_json::to_string_pretty(&exec.profile()).map_err(|err| format!("error: {err:?}"))
+}
+
+/// what can be typed at a cursor on `line` and `column`, both 1-based:
+/// primitives and their aliases, the program's procedures and, after a `:`,
+/// the inputs of the procedure the cursor is in
+///
+/// # Errors
+///
+/// This function will return an error if the code can't be tokenized.
+#[wasm_bindgen]
+pub fn completions(
+    opts: &Opts,
+    code: &str,
+    line: usize,
+    column: usize,
+) -> Result<JsValue, JsValue> {
+    let completions = service::completions(code, opts.locale(), line, column)
+        .map_err(|err| JsValue::from(opts.locale().message(&err)))?;
+    to_js(&completions)
+}
+
+/// the signature and description of the primitive at a cursor, or where the
+/// procedure or input there comes from; `null` over anything else
+///
+/// # Errors
+///
+/// This function will return an error if the code can't be tokenized.
+#[wasm_bindgen]
+pub fn hover(opts: &Opts, code: &str, line: usize, column: usize) -> Result<JsValue, JsValue> {
+    let hover = service::hover(code, opts.locale(), line, column)
+        .map_err(|err| JsValue::from(opts.locale().message(&err)))?;
+    to_js(&hover)
+}
+
+/// the span of the definition of the procedure or input at a cursor, or
+/// `null`
+///
+/// # Errors
+///
+/// This function will return an error if the code can't be tokenized.
+#[wasm_bindgen]
+pub fn definition(opts: &Opts, code: &str, line: usize, column: usize) -> Result<JsValue, JsValue> {
+    let span = service::definition(code, opts.locale(), line, column)
+        .map_err(|err| JsValue::from(opts.locale().message(&err)))?;
+    to_js(&span)
+}
+
+/// the spans of the definition and every use of the procedure or input at a
+/// cursor
+///
+/// # Errors
+///
+/// This function will return an error if the code can't be tokenized.
+#[wasm_bindgen]
+pub fn references(opts: &Opts, code: &str, line: usize, column: usize) -> Result<JsValue, JsValue> {
+    let spans = service::references(code, opts.locale(), line, column)
+        .map_err(|err| JsValue::from(opts.locale().message(&err)))?;
+    to_js(&spans)
+}
+
+/// what `draw` hands back once the drawing is on the canvas: what the
+/// program printed, and warnings about the code, as `run` has them
+#[derive(Serialize)]
+struct DrawResult {
+    output: Vec<String>,
+    warnings: Vec<Diagnostic>,
+}
+
+/// draw direct to canvas, and return what was printed and warnings
 ///
 /// # Panics
 ///
@@ -58,26 +263,19 @@ pub fn run(opts: &Opts, code: &str) -> Result<JsValue, JsValue> {
 ///
 /// # Errors
 ///
-/// This function will return an error if .
+/// This function will return an error if the program fails to parse or run,
+/// as `{message, warnings}`.
 #[wasm_bindgen]
-pub fn draw(opts: &Opts, code: &str) -> Result<(), JsValue> {
+pub fn draw(opts: &Opts, code: &str) -> Result<JsValue, JsValue> {
     //console::log_1(&format!("opts: {opts:?}").into());
-    let document = web_sys::window().unwrap().document().unwrap();
-    let canvas = document.get_element_by_id(&opts.canvas_id).unwrap();
-    let canvas: web_sys::HtmlCanvasElement = canvas
-        .dyn_into::<web_sys::HtmlCanvasElement>()
-        .map_err(|_| ())
-        .unwrap();
-
-    let context = canvas
-        .get_context("2d")
-        .unwrap()
-        .unwrap()
-        .dyn_into::<web_sys::CanvasRenderingContext2d>()
-        .unwrap();
+    let (canvas, context) = canvas_context(&opts.canvas_id);
 
     let mut exec = Executor::new();
+    exec.set_locale(opts.locale());
+    exec.start_lint();
     let mut tt = logo::turtle::Turtle::new((opts.x, opts.y), opts.budget);
+    tt.set_speed(opts.speed);
+    tt.canvas = Some((canvas.width() as f32, canvas.height() as f32));
     match exec.run(&mut tt, code) {
         Ok(_) => {
             let cmds = tt.commands();
@@ -86,12 +284,54 @@ pub fn draw(opts: &Opts, code: &str) -> Result<(), JsValue> {
                 &format!("render into: ({},{})", canvas.width(), canvas.height()).into(),
             );
             */
-            let mut plotter = CanvasPlotter::new(&context, (canvas.width(), canvas.height()));
+            let mut plotter =
+                CanvasPlotter::new(context, (canvas.width(), canvas.height()), opts.zoom);
             plotter.plot(&cmds);
 
-            Ok(())
+            to_js(&DrawResult {
+                output: exec.output(),
+                warnings: exec.warnings(),
+            })
             // console::log_1(&format!("{cmds:?}").into());
         }
-        Err(err) => Err(format!("{err:?}").into()),
+        Err(err) => Err(run_error(opts, &exec, &err)),
     }
 }
+
+/// the width and height of the canvas with element id `canvas_id`, if
+/// there is one, for `FENCE` to keep turtles on
+fn canvas_size(canvas_id: &str) -> Option<(f32, f32)> {
+    let canvas = web_sys::window()?
+        .document()?
+        .get_element_by_id(canvas_id)?
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .ok()?;
+    Some((canvas.width() as f32, canvas.height() as f32))
+}
+
+/// look up a canvas and its 2d context by element id
+///
+/// # Panics
+///
+/// Panics if dom ops are invalid
+fn canvas_context(
+    canvas_id: &str,
+) -> (
+    web_sys::HtmlCanvasElement,
+    web_sys::CanvasRenderingContext2d,
+) {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let canvas = document.get_element_by_id(canvas_id).unwrap();
+    let canvas: web_sys::HtmlCanvasElement = canvas
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .map_err(|_| ())
+        .unwrap();
+
+    let context = canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<web_sys::CanvasRenderingContext2d>()
+        .unwrap();
+    (canvas, context)
+}
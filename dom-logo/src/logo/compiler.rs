@@ -0,0 +1,570 @@
+// Compiles the AST into a flat bytecode for the executor's VM.
+//
+// Identifiers are interned into symbols, and parameters resolve to slots of
+// the frame running the code. Blocks are laid out inline with jumps, so
+// running a REPEAT or a procedure never copies expressions around.
+//
+use super::parser::{Expression, Primitive, Span};
+use super::turtle::Shape;
+use super::value::Value;
+use std::collections::HashMap;
+
+/// An interned identifier
+pub type Sym = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MathOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Less,
+    Gtr,
+    Eq,
+    Neq,
+}
+
+/// What a template is run by, which decides its slots and whether it is
+/// statements or an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Template {
+    Map,
+    Filter,
+    Reduce,
+    Foreach,
+    /// APPLY, on so many inputs
+    Apply(usize),
+}
+
+/// Slots of an APPLY template, where `?` is the first input like `?1`
+const APPLY_SLOTS: [&str; 10] = ["?", "?1", "?2", "?3", "?4", "?5", "?6", "?7", "?8", "?9"];
+
+impl Template {
+    /// Slot names a template can refer to
+    pub fn slots(self) -> &'static [&'static str] {
+        match self {
+            Self::Reduce => &["?1", "?2"],
+            Self::Apply(0) => &[],
+            Self::Apply(inputs) => &APPLY_SLOTS[..=inputs.min(9)],
+            _ => &["?"],
+        }
+    }
+
+    /// Slots a word naming a procedure or primitive is given as inputs
+    pub fn inputs(self) -> &'static [&'static str] {
+        match self {
+            Self::Apply(0) => &[],
+            Self::Apply(_) => &self.slots()[1..],
+            _ => self.slots(),
+        }
+    }
+
+    /// Whether the template is statements, rather than an expression
+    pub fn commands(self) -> bool {
+        matches!(self, Self::Foreach | Self::Apply(_))
+    }
+}
+
+/// A VM instruction. Reporters push a value; commands pop their inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Number(f32),
+    Const(u32),
+    Load(u32),
+    Unbound(Sym),
+    Undefined(u32),
+    NoSuchOperator(u32),
+    Who,
+    Repcount,
+    Error,
+    ToNumber,
+    Math(MathOp),
+    Compare(CompareOp),
+    Primitive(Primitive, u32),
+    /// Compiles the template on top of the stack for the next MAP, FILTER,
+    /// REDUCE or FOREACH
+    Template(Template),
+    /// Start a MAP, FILTER or REDUCE, which the following `Fold` runs an
+    /// item at a time
+    Map,
+    Filter,
+    Reduce,
+    Fold,
+
+    Clear,
+    Penup,
+    Pendown,
+    Setpos,
+    Setcolor,
+    Forward,
+    Back,
+    Right,
+    Left,
+    Arc,
+    Circle,
+    Setpensize,
+    Showturtle,
+    Hideturtle,
+    Setshape(u32),
+    Stamp,
+    Fence,
+    Window,
+    Tell(u32),
+    Setturtle,
+    Setspeed,
+    Wait,
+    Label,
+    Print,
+    Show,
+    Type,
+
+    Jump(u32),
+    JumpUnless(u32),
+    /// Starts a REPEAT, which the following `Next` loops on
+    Repeat,
+    Next(u32),
+    AskBegin(u32),
+    AskEnd,
+    Define(u32),
+    Call(Sym, u32),
+    TailCall(Sym, u32),
+    Apply,
+    Run,
+    /// Starts a FOREACH, which the following `Each` runs an item at a time
+    Foreach,
+    Each,
+    /// Starts a CATCH, which a THROW or an error in it resumes after
+    CatchBegin(u32),
+    CatchEnd,
+    /// Throws the tag on top of the stack, with a value under it if `true`
+    Throw(bool),
+    Return,
+}
+
+/// A compiled procedure
+#[derive(Debug, Clone)]
+pub struct Proc {
+    pub name: Sym,
+    pub arity: usize,
+    pub scope: u32,
+    pub entry: u32,
+    /// Where the body's code ends
+    pub end: u32,
+}
+
+/// How much code, and how many table entries, a program had at some point,
+/// for it to go back to
+#[derive(Debug, Clone, Copy)]
+pub struct Mark {
+    pub code: u32,
+    consts: usize,
+    shapes: usize,
+    spans: usize,
+    procs: usize,
+}
+
+/// Compiled code, and the tables its instructions index into
+#[derive(Debug, Default, Clone)]
+pub struct Program {
+    pub code: Vec<Op>,
+    pub consts: Vec<Value>,
+    pub shapes: Vec<Shape>,
+    pub procs: Vec<Proc>,
+    /// Names of the slots of a frame, by scope id
+    pub scopes: Vec<Vec<Sym>>,
+    /// Where each statement's code starts, in code order, and its place in
+    /// the source. Code parsed at run time has no place in the source.
+    pub spans: Vec<(u32, Option<Span>)>,
+    names: Vec<String>,
+    syms: HashMap<String, Sym>,
+    scope_ids: HashMap<Vec<Sym>, u32>,
+    /// Bodies of procedures defined within code being compiled, laid out
+    /// once that code is done
+    pending: Vec<(u32, Vec<Expression>)>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        let mut program = Self::default();
+        // the top level has no parameters
+        program.scope(vec![]);
+        program
+    }
+
+    /// Names are matched whatever their case, and keep the first spelling
+    /// seen
+    pub fn intern(&mut self, name: &str) -> Sym {
+        let key = name.to_lowercase();
+        if let Some(sym) = self.syms.get(&key) {
+            return *sym;
+        }
+        let sym = self.names.len() as Sym;
+        self.names.push(name.to_string());
+        self.syms.insert(key, sym);
+        sym
+    }
+
+    pub fn name(&self, sym: Sym) -> &str {
+        &self.names[sym as usize]
+    }
+
+    pub fn symbols(&self) -> usize {
+        self.names.len()
+    }
+
+    /// The id of a scope with the given slot names
+    pub fn scope(&mut self, slots: Vec<Sym>) -> u32 {
+        if let Some(id) = self.scope_ids.get(&slots) {
+            return *id;
+        }
+        let id = self.scopes.len() as u32;
+        self.scopes.push(slots.clone());
+        self.scope_ids.insert(slots, id);
+        id
+    }
+
+    /// Compiles statements run in a frame of `scope`, returning their entry
+    pub fn compile(&mut self, exps: &[Expression], scope: u32) -> u32 {
+        let entry = self.code.len() as u32;
+        self.block(exps, scope, false);
+        self.code.push(Op::Return);
+        self.flush();
+        entry
+    }
+
+    /// Compiles an expression that leaves its value for the caller
+    pub fn compile_reporter(&mut self, exp: &Expression, scope: u32) -> u32 {
+        let entry = self.code.len() as u32;
+        self.reporter(exp, scope);
+        self.code.push(Op::Return);
+        self.flush();
+        entry
+    }
+
+    pub fn compile_proc(&mut self, name: &str, params: &[String], body: &[Expression]) -> u32 {
+        let proc = self.declare(name, params, body.to_vec());
+        self.flush();
+        proc
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark {
+            code: self.code.len() as u32,
+            consts: self.consts.len(),
+            shapes: self.shapes.len(),
+            spans: self.spans.len(),
+            procs: self.procs.len(),
+        }
+    }
+
+    /// Whether procedures were declared since `mark`, whose code must stay
+    pub fn declared_since(&self, mark: &Mark) -> bool {
+        self.procs.len() > mark.procs
+    }
+
+    /// Forgets the code compiled since `mark`, which nothing may run anymore
+    pub fn truncate(&mut self, mark: &Mark) {
+        self.code.truncate(mark.code as usize);
+        self.consts.truncate(mark.consts);
+        self.shapes.truncate(mark.shapes);
+        self.spans.truncate(mark.spans);
+    }
+
+    /// Forgets the source spans recorded since `mark`, for code from `entry`
+    /// on that was parsed at run time
+    pub fn unmark(&mut self, mark: &Mark, entry: u32) {
+        self.spans.truncate(mark.spans);
+        self.spans.push((entry, None));
+    }
+
+    /// The source of the statement the code at `pc` belongs to
+    pub fn span_at(&self, pc: u32) -> Option<&Span> {
+        let i = self.spans.partition_point(|(start, _)| *start <= pc);
+        i.checked_sub(1).and_then(|i| self.spans[i].1.as_ref())
+    }
+
+    /// The source of the statement starting at `pc`, if one does
+    pub fn statement_at(&self, pc: u32) -> Option<&Span> {
+        let i = self.spans.partition_point(|(start, _)| *start <= pc);
+        match i.checked_sub(1).map(|i| &self.spans[i]) {
+            Some((start, span)) if *start == pc => span.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The procedure whose body holds the code at `pc`
+    pub fn proc_at(&self, pc: u32) -> Option<&Proc> {
+        self.procs
+            .iter()
+            .find(|proc| proc.entry <= pc && pc < proc.end)
+    }
+
+    /// Adds a procedure whose body is compiled by the next `flush`
+    fn declare(&mut self, name: &str, params: &[String], body: Vec<Expression>) -> u32 {
+        let name = self.intern(name);
+        let slots = params.iter().map(|param| self.intern(param)).collect();
+        let scope = self.scope(slots);
+        self.procs.push(Proc {
+            name,
+            arity: params.len(),
+            scope,
+            entry: 0,
+            end: 0,
+        });
+        let proc = self.procs.len() as u32 - 1;
+        self.pending.push((proc, body));
+        proc
+    }
+
+    fn flush(&mut self) {
+        while let Some((proc, body)) = self.pending.pop() {
+            let entry = self.code.len() as u32;
+            let scope = self.procs[proc as usize].scope;
+            self.block(&body, scope, true);
+            self.code.push(Op::Return);
+            self.procs[proc as usize].entry = entry;
+            self.procs[proc as usize].end = self.code.len() as u32;
+        }
+    }
+
+    /// In `tail` position, a final call replaces the running procedure
+    fn block(&mut self, exps: &[Expression], scope: u32, tail: bool) {
+        for (i, e) in exps.iter().enumerate() {
+            self.statement(e, scope, tail && i + 1 == exps.len());
+        }
+    }
+
+    fn statement(&mut self, e: &Expression, scope: u32, tail: bool) {
+        let op = match e {
+            Expression::Source(span) => {
+                self.spans
+                    .push((self.code.len() as u32, Some(span.clone())));
+                return;
+            }
+            Expression::Clear => Op::Clear,
+            Expression::Penup => Op::Penup,
+            Expression::Pendown => Op::Pendown,
+            Expression::Setpos(x, y) => {
+                self.number(x, scope);
+                self.number(y, scope);
+                Op::Setpos
+            }
+            Expression::Setcolor(r, g, b) => {
+                self.number(r, scope);
+                self.number(g, scope);
+                self.number(b, scope);
+                Op::Setcolor
+            }
+            Expression::Forward(arg) => self.with(arg, scope, Op::Forward),
+            Expression::Back(arg) => self.with(arg, scope, Op::Back),
+            Expression::Right(arg) => self.with(arg, scope, Op::Right),
+            Expression::Left(arg) => self.with(arg, scope, Op::Left),
+            Expression::Arc(angle, radius) => {
+                self.number(angle, scope);
+                self.number(radius, scope);
+                Op::Arc
+            }
+            Expression::Circle(arg) => self.with(arg, scope, Op::Circle),
+            Expression::Setpensize(arg) => self.with(arg, scope, Op::Setpensize),
+            Expression::Showturtle => Op::Showturtle,
+            Expression::Hideturtle => Op::Hideturtle,
+            Expression::Setshape(shape) => {
+                self.shapes.push(shape.clone());
+                Op::Setshape(self.shapes.len() as u32 - 1)
+            }
+            Expression::Stamp => Op::Stamp,
+            Expression::Fence => Op::Fence,
+            Expression::Window => Op::Window,
+            Expression::Tell(ids) => {
+                for id in ids {
+                    self.reporter(id, scope);
+                }
+                Op::Tell(ids.len() as u32)
+            }
+            Expression::Setturtle(id) => self.with(id, scope, Op::Setturtle),
+            Expression::Setspeed(arg) => self.with(arg, scope, Op::Setspeed),
+            Expression::Wait(arg) => self.with(arg, scope, Op::Wait),
+            Expression::Label(arg) => self.with(arg, scope, Op::Label),
+            Expression::Print(arg) => self.with(arg, scope, Op::Print),
+            Expression::Show(arg) => self.with(arg, scope, Op::Show),
+            Expression::Type(arg) => self.with(arg, scope, Op::Type),
+            Expression::Run(list) => self.with(list, scope, Op::Run),
+            Expression::Apply(name, inputs) => {
+                self.reporter(name, scope);
+                self.reporter(inputs, scope);
+                Op::Apply
+            }
+            Expression::Foreach(list, template) => {
+                self.reporter(list, scope);
+                self.reporter(template, scope);
+                self.code.push(Op::Template(Template::Foreach));
+                self.code.push(Op::Foreach);
+                Op::Each
+            }
+            Expression::Catch(tag, exps) => {
+                self.reporter(tag, scope);
+                let begin = self.code.len();
+                self.code.push(Op::CatchBegin(0));
+                self.block(exps, scope, false);
+                self.code.push(Op::CatchEnd);
+                self.code[begin] = Op::CatchBegin(self.code.len() as u32);
+                return;
+            }
+            Expression::Throw(tag, value) => {
+                if let Some(value) = value {
+                    self.reporter(value, scope);
+                }
+                self.with(tag, scope, Op::Throw(value.is_some()))
+            }
+            Expression::Ask(ids, exps) => {
+                for id in ids {
+                    self.reporter(id, scope);
+                }
+                self.code.push(Op::AskBegin(ids.len() as u32));
+                self.block(exps, scope, false);
+                Op::AskEnd
+            }
+            Expression::Repeat(count, exps) => {
+                self.reporter(count, scope);
+                self.code.push(Op::Repeat);
+                let next = self.code.len();
+                self.code.push(Op::Next(0));
+                self.block(exps, scope, false);
+                self.code.push(Op::Jump(next as u32));
+                self.code[next] = Op::Next(self.code.len() as u32);
+                return;
+            }
+            Expression::If(condition, exps) => {
+                self.reporter(condition, scope);
+                let jump = self.code.len();
+                self.code.push(Op::JumpUnless(0));
+                // the branch ends the block too, so it can end with a tail call
+                self.block(exps, scope, tail);
+                self.code[jump] = Op::JumpUnless(self.code.len() as u32);
+                return;
+            }
+            Expression::To(name, params, body) => {
+                Op::Define(self.declare(name, params, body.clone()))
+            }
+            Expression::Call(name, args) => {
+                for arg in args {
+                    self.reporter(arg, scope);
+                }
+                let (sym, argc) = (self.intern(name), args.len() as u32);
+                if tail {
+                    Op::TailCall(sym, argc)
+                } else {
+                    Op::Call(sym, argc)
+                }
+            }
+            _ => return,
+        };
+        self.code.push(op);
+    }
+
+    /// Compiles `arg` followed by the instruction consuming it
+    fn with(&mut self, arg: &Expression, scope: u32, op: Op) -> Op {
+        self.reporter(arg, scope);
+        op
+    }
+
+    /// An input that is read as a number before the next one is evaluated
+    fn number(&mut self, exp: &Expression, scope: u32) {
+        self.reporter(exp, scope);
+        if !matches!(exp, Expression::Number(_) | Expression::Math(..)) {
+            self.code.push(Op::ToNumber);
+        }
+    }
+
+    fn reporter(&mut self, exp: &Expression, scope: u32) {
+        let op = match exp {
+            Expression::Number(n) => Op::Number(*n),
+            Expression::Word(w) => self.constant(Value::Word(w.clone())),
+            Expression::List(items) => self.constant(Value::List(items.clone())),
+            Expression::Var(id) => {
+                let sym = self.intern(id);
+                // later slots, such as a template's `?`, hide earlier ones
+                match self.scopes[scope as usize].iter().rposition(|s| *s == sym) {
+                    Some(slot) => Op::Load(slot as u32),
+                    None => Op::Unbound(sym),
+                }
+            }
+            Expression::Who => Op::Who,
+            Expression::Repcount => Op::Repcount,
+            Expression::Error => Op::Error,
+            Expression::Math(lhs, op, rhs) => {
+                self.number(lhs, scope);
+                self.reporter(rhs, scope);
+                match **op {
+                    Expression::Add => Op::Math(MathOp::Add),
+                    Expression::Sub => Op::Math(MathOp::Sub),
+                    Expression::Mul => Op::Math(MathOp::Mul),
+                    Expression::Div => Op::Math(MathOp::Div),
+                    ref other => {
+                        self.consts.push(Value::Word(format!("{other:?}")));
+                        Op::NoSuchOperator(self.consts.len() as u32 - 1)
+                    }
+                }
+            }
+            Expression::Condition(lhs, op, rhs) => {
+                self.reporter(lhs, scope);
+                self.reporter(rhs, scope);
+                match **op {
+                    Expression::Less => Op::Compare(CompareOp::Less),
+                    Expression::Gtr => Op::Compare(CompareOp::Gtr),
+                    Expression::Eq => Op::Compare(CompareOp::Eq),
+                    Expression::Neq => Op::Compare(CompareOp::Neq),
+                    ref other => self.undefined(other),
+                }
+            }
+            Expression::Primitive(p, inputs) => {
+                for input in inputs {
+                    self.reporter(input, scope);
+                }
+                Op::Primitive(*p, inputs.len() as u32)
+            }
+            Expression::Map(template, list) => self.template(template, list, scope, Template::Map),
+            Expression::Filter(template, list) => {
+                self.template(template, list, scope, Template::Filter)
+            }
+            Expression::Reduce(template, list) => {
+                self.template(template, list, scope, Template::Reduce)
+            }
+            other => self.undefined(other),
+        };
+        self.code.push(op);
+    }
+
+    fn template(
+        &mut self,
+        template: &Expression,
+        list: &Expression,
+        scope: u32,
+        kind: Template,
+    ) -> Op {
+        self.reporter(template, scope);
+        self.code.push(Op::Template(kind));
+        self.reporter(list, scope);
+        self.code.push(match kind {
+            Template::Map => Op::Map,
+            Template::Filter => Op::Filter,
+            _ => Op::Reduce,
+        });
+        Op::Fold
+    }
+
+    fn constant(&mut self, value: Value) -> Op {
+        self.consts.push(value);
+        Op::Const(self.consts.len() as u32 - 1)
+    }
+
+    /// An expression that can't be evaluated fails when it is reached
+    fn undefined(&mut self, exp: &Expression) -> Op {
+        self.consts.push(Value::Word(format!("{exp:?}")));
+        Op::Undefined(self.consts.len() as u32 - 1)
+    }
+}
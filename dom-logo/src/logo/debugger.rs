@@ -0,0 +1,72 @@
+use super::parser::Span;
+use super::turtle::{Record, TurtleState};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+/// How far a paused program runs before pausing again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Until a breakpoint
+    Continue,
+    /// Until the next statement, inside a procedure being called too
+    StepInto,
+    /// Until the next statement of the running frame or its callers
+    StepOver,
+    /// Until the next statement of a caller
+    StepOut,
+}
+
+/// Where the executor pauses, besides breakpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Until {
+    Breakpoint,
+    Statement,
+    /// A statement of a frame at most this deep
+    Depth(usize),
+}
+
+/// Breakpoints and stepping state of a program being debugged
+#[derive(Debug, Clone)]
+pub(crate) struct Debugger {
+    /// Source lines to pause on, 1-based
+    pub breakpoints: HashSet<usize>,
+    pub until: Until,
+    pub paused: bool,
+    /// Run the next instruction without pausing, since we just resumed there
+    pub resumed: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: HashSet::new(),
+            until: Until::Breakpoint,
+            paused: false,
+            resumed: false,
+        }
+    }
+}
+
+/// A frame on the call stack, as the debugger shows it
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameSnapshot {
+    /// The procedure running, if it is one rather than the top level, a RUN
+    /// list or a template
+    pub procedure: Option<String>,
+    /// The statement running, when it comes from the program's source
+    pub span: Option<Span>,
+    /// Inputs the frame sees, by name, as SHOW would write them
+    pub args: BTreeMap<String, String>,
+}
+
+/// What the debugger shows when a program pauses
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    /// The call stack, innermost frame last
+    pub frames: Vec<FrameSnapshot>,
+    /// The first turtle listening to commands, and its state
+    pub turtle: usize,
+    pub state: TurtleState,
+    /// The last command emitted
+    pub last: Option<Record>,
+}
@@ -1,197 +1,1761 @@
-use super::parser::{parse, Expression};
-use super::turtle::Turtle;
-use super::LogoError;
-use std::collections::HashMap;
+use super::compiler::{CompareOp, Mark, MathOp, Op, Program, Sym, Template};
+use super::debugger::{Debugger, FrameSnapshot, Resume, Snapshot, Until};
+use super::linter::lint;
+use super::locale::{Locale, ENGLISH};
+use super::parser::{called, parse_expression, parse_with, Expression, Signatures};
+use super::primitives;
+use super::profiler::{Profile, Profiler};
+use super::turtle::{Caller, Origin, Turtle};
+use super::value::Value;
+use super::{Diagnostic, LogoError};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+/// Compiled code the VM can enter: where it starts, and the names of the
+/// slots it reads
+#[derive(Clone, Copy, Debug)]
+struct Chunk {
+    entry: u32,
+    scope: u32,
+}
+
+/// A procedure, RUN list or template being run
+#[derive(Clone, Debug)]
+struct Frame {
+    pc: u32,
+    locals: Vec<Value>,
+    scope: u32,
+    /// Procedure calls this frame is nested in
+    depth: usize,
+}
+
+/// A REPEAT being run
+#[derive(Clone, Debug)]
+struct Loop {
+    count: usize,
+    total: usize,
+}
+
+/// A FOREACH being run
+#[derive(Clone, Debug)]
+struct Iteration {
+    items: Vec<Value>,
+    next: usize,
+    chunk: Chunk,
+}
+
+/// A MAP, FILTER or REDUCE being run
+#[derive(Clone, Debug)]
+struct Fold {
+    kind: Template,
+    /// The items, last first for REDUCE, which folds from the right
+    items: Vec<Value>,
+    next: usize,
+    chunk: Chunk,
+    /// Items mapped or kept so far, or the value REDUCE folded so far
+    done: Vec<Value>,
+    /// Whether the template's value for the item before `next` is on the
+    /// stack
+    running: bool,
+}
+
+/// A CATCH being run, and how much of the VM's state to keep when unwinding
+/// to it
+#[derive(Clone, Debug)]
+struct Catch {
+    tag: String,
+    end: u32,
+    frames: usize,
+    stack: usize,
+    loops: usize,
+    iterations: usize,
+    folds: usize,
+    templates: usize,
+    asks: usize,
+}
+
+/// Code parsed at run time, by source, scope and template kind
+type CompiledKey = (String, u32, Option<Template>);
+
+/// A chunk of code parsed at run time, with the procedures it calls, whose
+/// definitions decide how it parses
+#[derive(Clone, Debug)]
+struct Compiled {
+    chunk: Chunk,
+    calls: HashSet<String>,
+}
+
+/// Most chunks of code parsed at run time kept. Past that, those no longer
+/// running are forgotten, code and all, and parsed again if they come back.
+const MAX_COMPILED: usize = 256;
+
+/// Runs Logo on a stack VM, compiling the code to bytecode first
 #[derive(Clone)]
 pub struct Executor {
-    functions: HashMap<String, Function>,
+    program: Program,
+    /// Procedure defined for each symbol, if any
+    defined: Vec<Option<u32>>,
+    /// Chunks of code parsed at run time, for reuse until a procedure they
+    /// call is defined
+    compiled: HashMap<CompiledKey, Compiled>,
+    /// Where each chunk parsed at run time starts, in order, for its code to
+    /// be forgotten. Chunks defining procedures stay for good, with those
+    /// before them.
+    runtime: Vec<Mark>,
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+    loops: Vec<Loop>,
+    iterations: Vec<Iteration>,
+    folds: Vec<Fold>,
+    templates: Vec<Chunk>,
+    /// Turtles active before each ASK being run
+    asks: Vec<Vec<usize>>,
+    catches: Vec<Catch>,
+    /// The last error caught, for ERROR to report
+    error: Option<Value>,
     recursion_budget: usize,
+    output: String,
+    warnings: Vec<Diagnostic>,
+    /// Instructions run so far, held to the turtle's budget
+    steps: usize,
+    debugger: Option<Debugger>,
+    profiler: Option<Profiler>,
+    /// Programs loaded are linted, as the editor wants but RUN doesn't
+    lint: bool,
+    /// Language the code is parsed in, besides English
+    locale: &'static Locale,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Self {
-            functions: HashMap::new(),
+            program: Program::new(),
+            defined: vec![],
+            compiled: HashMap::new(),
+            runtime: vec![],
+            frames: vec![],
+            stack: vec![],
+            loops: vec![],
+            iterations: vec![],
+            folds: vec![],
+            templates: vec![],
+            asks: vec![],
+            catches: vec![],
+            error: None,
             recursion_budget: 1000,
+            output: String::new(),
+            warnings: vec![],
+            steps: 0,
+            debugger: None,
+            profiler: None,
+            lint: false,
+            locale: &ENGLISH,
         }
     }
 
+    /// Lines written by PRINT, SHOW and TYPE so far
+    pub fn output(&self) -> Vec<String> {
+        self.output.lines().map(String::from).collect()
+    }
+
+    /// Warnings about the code run so far
+    pub fn warnings(&self) -> Vec<Diagnostic> {
+        self.warnings.clone()
+    }
+
+    /// Parses code from here on with `locale`'s keywords, besides English
+    pub fn set_locale(&mut self, locale: &'static Locale) {
+        self.locale = locale;
+    }
+
+    /// Runs a program to completion
     pub fn run(&mut self, turtle: &mut Turtle, logo: &str) -> Result<(), LogoError> {
-        let ast = parse(logo)?;
-        self.run_ast(ast, turtle, &None, 0)
-    }
-    fn run_ast(
-        &mut self,
-        ast: Vec<Expression>,
-        turtle: &mut Turtle,
-        args: &Option<HashMap<String, f32>>,
-        depth: usize,
-    ) -> Result<(), LogoError> {
-        if depth > self.recursion_budget {
-            return Err(LogoError::RecursionBudgetExceeded(self.recursion_budget));
+        self.load(logo)?;
+        while !self.step(turtle, usize::MAX)? {}
+        Ok(())
+    }
+
+    /// Compiles a program and gets ready to run it. Its top level procedures
+    /// are defined up front, so they can be called before their `TO`.
+    pub fn load(&mut self, logo: &str) -> Result<(), LogoError> {
+        let (ast, warnings) = parse_with(logo, &self.signatures(), self.locale)?;
+        self.warnings.extend(warnings);
+        if self.lint {
+            self.warnings.extend(lint(logo, &ast));
         }
+        let mut statements = vec![];
         for e in ast {
             match e {
-                Expression::Clear => turtle.clear(),
-                Expression::Penup => turtle.pendown = false,
-                Expression::Pendown => turtle.pendown = true,
-                Expression::Setpos(x, y) => {
-                    turtle.position(self.eval_arg(&x, args)?, self.eval_arg(&y, args)?)?;
+                Expression::To(id, args, exp) => {
+                    let proc = self.program.compile_proc(&id, &args, &exp);
+                    self.define(proc);
+                }
+                e => statements.push(e),
+            }
+        }
+        let entry = self.program.compile(&statements, 0);
+        // the program is laid out after code parsed at run time before it
+        self.runtime.clear();
+        self.frames.push(Frame {
+            pc: entry,
+            locals: vec![],
+            scope: 0,
+            depth: 0,
+        });
+        Ok(())
+    }
+
+    /// Runs up to `max` instructions, and tells whether the program is done.
+    /// When debugging, it stops short at a pause.
+    pub fn step(&mut self, turtle: &mut Turtle, max: usize) -> Result<bool, LogoError> {
+        for _ in 0..max {
+            if self.frames.is_empty() || self.pausing() {
+                break;
+            }
+            let emitted = turtle.commands.len();
+            if self.profiler.is_some() {
+                let pc = self.source_pc();
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.step(pc);
                 }
-                Expression::Setcolor(r, g, b) => {
-                    turtle.setcolor(self.eval_color(&r, &g, &b, args)?);
+            }
+            let result = self.exec(turtle);
+            if let Some(profiler) = &mut self.profiler {
+                profiler.emitted(turtle.commands.len() - emitted);
+            }
+            if matches!(&turtle.trace, Some(trace) if trace.len() < turtle.commands.len()) {
+                turtle.traced(&self.origin());
+            }
+            if let Err(err) = result {
+                self.unwind(turtle, err)?;
+            }
+        }
+        Ok(self.frames.is_empty())
+    }
+
+    /// Lints the programs loaded from here on, adding to the warnings
+    pub fn start_lint(&mut self) {
+        self.lint = true;
+    }
+
+    /// Counts the work done by each procedure and source line from here on
+    pub fn start_profile(&mut self) {
+        self.profiler.get_or_insert_with(Profiler::default);
+    }
+
+    /// Work done by each procedure and source line, when profiling
+    pub fn profile(&self) -> Option<Profile> {
+        self.profiler
+            .as_ref()
+            .map(|profiler| profiler.report(&self.program))
+    }
+
+    /// Debugs the program from here on, pausing before statements on `lines`
+    pub fn set_breakpoints(&mut self, lines: &[usize]) {
+        self.debugger.get_or_insert_with(Debugger::new).breakpoints =
+            lines.iter().copied().collect();
+    }
+
+    /// Whether the program is paused in the debugger
+    pub fn paused(&self) -> bool {
+        matches!(&self.debugger, Some(debugger) if debugger.paused)
+    }
+
+    /// Lets a paused program go on, to pause again as `how` says. Debugs the
+    /// program if it wasn't already, so stepping can start before running.
+    pub fn resume(&mut self, how: Resume) {
+        let depth = self.frames.len();
+        let debugger = self.debugger.get_or_insert_with(Debugger::new);
+        debugger.until = match how {
+            Resume::Continue => Until::Breakpoint,
+            Resume::StepInto => Until::Statement,
+            Resume::StepOver => Until::Depth(depth),
+            Resume::StepOut => Until::Depth(depth.saturating_sub(1)),
+        };
+        debugger.resumed = debugger.paused;
+        debugger.paused = false;
+    }
+
+    /// The call stack, the active turtle and the last command, for the
+    /// debugger to show
+    pub fn snapshot(&self, turtle: &Turtle) -> Snapshot {
+        let top = self.frames.len().saturating_sub(1);
+        let frames = self
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                // callers are past the instruction that called
+                let pc = if i == top { frame.pc } else { frame.pc - 1 };
+                let names = &self.program.scopes[frame.scope as usize];
+                FrameSnapshot {
+                    procedure: self
+                        .program
+                        .proc_at(pc)
+                        .map(|proc| self.program.name(proc.name).to_string()),
+                    span: self.program.span_at(pc).cloned(),
+                    args: names
+                        .iter()
+                        .zip(&frame.locals)
+                        .map(|(sym, value)| {
+                            (self.program.name(*sym).to_string(), value.to_string())
+                        })
+                        .collect::<BTreeMap<_, _>>(),
                 }
-                Expression::Forward(arg) => turtle.forward(self.eval_arg(&arg, args)?)?,
-                Expression::Back(arg) => turtle.back(self.eval_arg(&arg, args)?)?,
-                Expression::Right(arg) => turtle.right(self.eval_arg(&arg, args)?)?,
-                Expression::Left(arg) => turtle.left(self.eval_arg(&arg, args)?)?,
-                Expression::Arc(x, y) => {
-                    turtle.arc(self.eval_arg(&x, args)?, self.eval_arg(&y, args)?)?;
+            })
+            .collect();
+        let who = turtle.who();
+        Snapshot {
+            frames,
+            turtle: who,
+            state: turtle.turtles[&who].clone(),
+            last: turtle.commands.last().cloned(),
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_sign_loss)]
+    fn exec(&mut self, turtle: &mut Turtle) -> Result<(), LogoError> {
+        self.steps += 1;
+        if self.steps > turtle.budget {
+            return Err(LogoError::RuntimeBudgetExceeded(turtle.budget));
+        }
+        let frame = self.frames.last_mut().expect("a frame is running");
+        let op = self.program.code[frame.pc as usize];
+        frame.pc += 1;
+        match op {
+            Op::Number(n) => self.stack.push(Value::Number(n)),
+            Op::Const(i) => self.stack.push(self.program.consts[i as usize].clone()),
+            Op::Load(slot) => {
+                let value = self.frame().locals[slot as usize].clone();
+                self.stack.push(value);
+            }
+            Op::Unbound(sym) => {
+                return Err(LogoError::UndefinedParameter(self.program.name(sym).into()))
+            }
+            Op::Undefined(i) => {
+                return Err(LogoError::UndefinedExpression(
+                    self.program.consts[i as usize].to_string(),
+                ))
+            }
+            Op::NoSuchOperator(i) => {
+                return Err(LogoError::NoSuchOperator(
+                    self.program.consts[i as usize].to_string(),
+                ))
+            }
+            Op::Who => self.stack.push(match turtle.active.as_slice() {
+                [id] => Value::Number(*id as f32),
+                ids => Value::List(ids.iter().map(|id| Value::Number(*id as f32)).collect()),
+            }),
+            Op::Error => {
+                let error = self.error.take().unwrap_or(Value::List(vec![]));
+                self.stack.push(error);
+            }
+            // -1 outside of any REPEAT, like UCBLogo
+            Op::Repcount => self.stack.push(Value::Number(
+                self.loops.last().map_or(-1.0, |l| l.count as f32),
+            )),
+            Op::ToNumber => {
+                let n = self.pop().number()?;
+                self.stack.push(Value::Number(n));
+            }
+            Op::Math(op) => {
+                let b = self.pop().number()?;
+                let a = self.pop().number()?;
+                self.stack.push(Value::Number(match op {
+                    MathOp::Add => a + b,
+                    MathOp::Sub => a - b,
+                    MathOp::Mul => a * b,
+                    MathOp::Div => a / b,
+                }));
+            }
+            Op::Compare(op) => {
+                let b = self.pop();
+                let a = self.pop();
+                self.stack.push(Value::Bool(match op {
+                    CompareOp::Less => a.number()? < b.number()?,
+                    CompareOp::Gtr => a.number()? > b.number()?,
+                    CompareOp::Eq => a.same(&b),
+                    CompareOp::Neq => !a.same(&b),
+                }));
+            }
+            Op::Primitive(p, argc) => {
+                let inputs = self.pop_n(argc);
+                let value = primitives::apply(p, &inputs)?;
+                self.stack.push(value);
+            }
+            Op::Template(kind) => {
+                let template = self.pop();
+                let chunk = self.compile_template(&template, kind)?;
+                self.templates.push(chunk);
+            }
+            Op::Map | Op::Filter => {
+                let items = self.pop().list()?.to_vec();
+                let chunk = self.templates.pop().expect("template compiled");
+                let kind = if op == Op::Map {
+                    Template::Map
+                } else {
+                    Template::Filter
+                };
+                self.folds.push(Fold {
+                    kind,
+                    items,
+                    next: 0,
+                    chunk,
+                    done: vec![],
+                    running: false,
+                });
+            }
+            Op::Reduce => {
+                let list = self.pop();
+                let chunk = self.templates.pop().expect("template compiled");
+                // folds from the right, as UCBLogo does
+                let mut items = list.list()?.to_vec();
+                items.reverse();
+                let last = items
+                    .first()
+                    .cloned()
+                    .ok_or_else(|| LogoError::BadInput("reduce".into(), list.to_string()))?;
+                self.folds.push(Fold {
+                    kind: Template::Reduce,
+                    items,
+                    next: 1,
+                    chunk,
+                    done: vec![last],
+                    running: false,
+                });
+            }
+            Op::Fold => self.fold()?,
+
+            Op::Clear => turtle.clear()?,
+            Op::Penup => turtle.pendown(false),
+            Op::Pendown => turtle.pendown(true),
+            Op::Setpos => {
+                let y = self.pop().number()?;
+                let x = self.pop().number()?;
+                turtle.position(x, y)?;
+            }
+            Op::Setcolor => {
+                let b = self.pop().number()? as u8;
+                let g = self.pop().number()? as u8;
+                let r = self.pop().number()? as u8;
+                turtle.setcolor((r, g, b))?;
+            }
+            Op::Forward => turtle.forward(self.pop().number()?)?,
+            Op::Back => turtle.back(self.pop().number()?)?,
+            Op::Right => turtle.right(self.pop().number()?)?,
+            Op::Left => turtle.left(self.pop().number()?)?,
+            Op::Arc => {
+                let radius = self.pop().number()?;
+                let angle = self.pop().number()?;
+                turtle.arc(angle, radius)?;
+            }
+            Op::Circle => turtle.circle(self.pop().number()?)?,
+            Op::Setpensize => turtle.pensize(self.pop().number()?)?,
+            Op::Showturtle => turtle.showturtle(true)?,
+            Op::Hideturtle => turtle.showturtle(false)?,
+            Op::Setshape(i) => turtle.setshape(&self.program.shapes[i as usize])?,
+            Op::Stamp => turtle.stamp()?,
+            Op::Fence => turtle.fence(true),
+            Op::Window => turtle.fence(false),
+            Op::Tell(count) => {
+                let ids = self.turtle_ids(count)?;
+                turtle.tell(ids)?;
+            }
+            Op::Setturtle => {
+                let id = Self::turtle_id(&self.pop())?;
+                turtle.tell(vec![id])?;
+            }
+            Op::Setspeed => turtle.setspeed(self.pop().number()?)?,
+            Op::Wait => turtle.wait(self.pop().number()?)?,
+            Op::Label => turtle.label(self.pop().printed())?,
+            Op::Print => {
+                let text = self.pop().printed();
+                self.output.push_str(&text);
+                self.output.push('\n');
+            }
+            Op::Show => {
+                let text = self.pop().to_string();
+                self.output.push_str(&text);
+                self.output.push('\n');
+            }
+            Op::Type => {
+                let text = self.pop().printed();
+                self.output.push_str(&text);
+            }
+
+            Op::Jump(target) => self.frame_mut().pc = target,
+            Op::JumpUnless(target) => {
+                if !self.pop().boolean()? {
+                    self.frame_mut().pc = target;
                 }
-                Expression::Circle(x) => {
-                    turtle.circle(self.eval_arg(&x, args)?)?;
+            }
+            Op::Repeat => {
+                let total = self.pop().number()? as usize;
+                self.loops.push(Loop { count: 0, total });
+            }
+            Op::Next(end) => {
+                let repeat = self.loops.last_mut().expect("repeat started");
+                if repeat.count < repeat.total {
+                    repeat.count += 1;
+                } else {
+                    self.loops.pop();
+                    self.frame_mut().pc = end;
                 }
-                Expression::Setpensize(arg) => turtle.pensize(self.eval_arg(&arg, args)?)?,
-                Expression::Repeat(count, exp) => {
-                    let n = self.eval_arg(&count, args)?;
-                    #[allow(clippy::cast_possible_truncation)]
-                    #[allow(clippy::cast_sign_loss)]
-                    for _ in 0..n as usize {
-                        self.run_ast(exp.clone(), turtle, args, depth)?;
+            }
+            Op::AskBegin(count) => {
+                let ids = self.turtle_ids(count)?;
+                let previous = turtle.tell(ids)?;
+                self.asks.push(previous);
+            }
+            Op::AskEnd => turtle.active = self.asks.pop().expect("ask started"),
+            Op::Define(proc) => self.define(proc),
+            Op::Call(sym, argc) => {
+                let values = self.pop_n(argc);
+                self.call(sym, values, false)?;
+            }
+            Op::TailCall(sym, argc) => {
+                let values = self.pop_n(argc);
+                self.call(sym, values, true)?;
+            }
+            Op::Apply => {
+                let values = self.pop().list()?.to_vec();
+                let template = self.pop();
+                // a procedure is called as it is, anything else as a template
+                if let Value::Word(name) = &template {
+                    let sym = self.program.intern(name);
+                    if matches!(self.defined.get(sym as usize), Some(Some(_))) {
+                        return self.call(sym, values, false);
                     }
                 }
-                Expression::If(condition, exp) => {
-                    if self.eval_condition(*condition, args)? {
-                        self.run_ast(exp, turtle, args, depth)?;
+                let kind = Template::Apply(values.len());
+                let chunk = self.compile_template(&template, kind)?;
+                let slots: Vec<Value> =
+                    values.first().into_iter().chain(&values).cloned().collect();
+                self.enter(chunk, &slots[..kind.slots().len()]);
+            }
+            Op::Run => {
+                let code = self.pop().printed();
+                let chunk = self.compile_run(code)?;
+                let frame = self.frame();
+                let frame = Frame {
+                    pc: chunk.entry,
+                    locals: frame.locals.clone(),
+                    scope: chunk.scope,
+                    depth: frame.depth,
+                };
+                self.frames.push(frame);
+            }
+            Op::Foreach => {
+                let chunk = self.templates.pop().expect("template compiled");
+                let items = self.pop().list()?.to_vec();
+                self.iterations.push(Iteration {
+                    items,
+                    next: 0,
+                    chunk,
+                });
+            }
+            Op::Each => {
+                let iteration = self.iterations.last_mut().expect("foreach started");
+                match iteration.items.get(iteration.next).cloned() {
+                    Some(item) => {
+                        iteration.next += 1;
+                        let chunk = iteration.chunk;
+                        // come back here for the next item
+                        self.frame_mut().pc -= 1;
+                        self.enter(chunk, &[item]);
+                    }
+                    None => {
+                        self.iterations.pop();
                     }
                 }
-                Expression::To(id, args, exp) => self.add_function(id, Function::new(args, exp)),
-                Expression::Call(id, params) => {
-                    self.call_function(turtle, id.as_str(), &params, args, depth)?;
+            }
+            Op::CatchBegin(end) => {
+                let tag = self.pop().printed().to_lowercase();
+                self.catches.push(Catch {
+                    tag,
+                    end,
+                    frames: self.frames.len(),
+                    stack: self.stack.len(),
+                    loops: self.loops.len(),
+                    iterations: self.iterations.len(),
+                    folds: self.folds.len(),
+                    templates: self.templates.len(),
+                    asks: self.asks.len(),
+                });
+            }
+            Op::CatchEnd => {
+                self.catches.pop();
+            }
+            Op::Throw(valued) => {
+                let tag = self.pop().printed().to_lowercase();
+                let value = if valued { Some(self.pop()) } else { None };
+                return Err(LogoError::Throw(tag, value));
+            }
+            Op::Return => {
+                self.frames.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// The next instruction to run or, when it was parsed at run time, the
+    /// instruction that ran its code, so its work counts toward the source
+    fn source_pc(&self) -> u32 {
+        let top = self.frames.len() - 1;
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            let pc = if i == top { frame.pc } else { frame.pc - 1 };
+            if self.program.span_at(pc).is_some() {
+                return pc;
+            }
+        }
+        self.frames[top].pc
+    }
+
+    /// Where the instruction just run comes from in the source
+    fn origin(&self) -> Origin {
+        let mut origin = Origin::default();
+        // every frame is past the instruction it ran last
+        for frame in &self.frames {
+            let pc = frame.pc - 1;
+            if let Some(proc) = self.program.proc_at(pc) {
+                origin.calls.push(Caller {
+                    procedure: self.program.name(proc.name).to_string(),
+                    span: origin.span.clone(),
+                });
+            }
+            if let Some(span) = self.program.span_at(pc) {
+                origin.span = Some(span.clone());
+            }
+        }
+        origin
+    }
+
+    /// Resumes after the innermost CATCH for a THROW or, with the tag
+    /// `error`, for a failure. Running out of budget can't be caught.
+    fn unwind(&mut self, turtle: &mut Turtle, err: LogoError) -> Result<(), LogoError> {
+        let (tag, err) = match err {
+            LogoError::RuntimeBudgetExceeded(_) | LogoError::RecursionBudgetExceeded(_) => {
+                return Err(err)
+            }
+            LogoError::Throw(tag, value) if tag == "error" => (
+                tag,
+                LogoError::UserError(value.unwrap_or_else(|| Value::Word(String::new()))),
+            ),
+            LogoError::Throw(tag, value) => {
+                if let Some(i) = self.catches.iter().rposition(|c| c.tag == tag) {
+                    self.resume_at(turtle, i);
+                    return Ok(());
+                }
+                ("error".to_string(), LogoError::Throw(tag, value))
+            }
+            err => ("error".to_string(), err),
+        };
+        let i = match self.catches.iter().rposition(|c| c.tag == tag) {
+            Some(i) => i,
+            None => return Err(err),
+        };
+        let origin = self.origin();
+        let message = match err {
+            LogoError::UserError(value) => value,
+            err => Value::Word(err.to_string()),
+        };
+        self.error = Some(Value::List(vec![
+            message,
+            origin.calls.last().map_or(Value::List(vec![]), |call| {
+                Value::Word(call.procedure.clone())
+            }),
+            origin
+                .span
+                .map_or(Value::List(vec![]), |span| Value::Number(span.line as f32)),
+        ]));
+        self.resume_at(turtle, i);
+        Ok(())
+    }
+
+    /// Drops everything started within the `i`th CATCH, and goes on after it
+    fn resume_at(&mut self, turtle: &mut Turtle, i: usize) {
+        let catch = self.catches[i].clone();
+        self.catches.truncate(i);
+        self.frames.truncate(catch.frames);
+        self.frame_mut().pc = catch.end;
+        self.stack.truncate(catch.stack);
+        self.loops.truncate(catch.loops);
+        self.iterations.truncate(catch.iterations);
+        self.folds.truncate(catch.folds);
+        self.templates.truncate(catch.templates);
+        if self.asks.len() > catch.asks {
+            turtle.active = self.asks[catch.asks].clone();
+            self.asks.truncate(catch.asks);
+        }
+    }
+
+    /// Whether to hold off running the next instruction, pausing if it
+    /// starts a statement the debugger should stop at
+    fn pausing(&mut self) -> bool {
+        let debugger = match &mut self.debugger {
+            Some(debugger) => debugger,
+            None => return false,
+        };
+        if debugger.paused {
+            return true;
+        }
+        if std::mem::take(&mut debugger.resumed) {
+            return false;
+        }
+        let depth = self.frames.len();
+        let pc = self.frames[depth - 1].pc;
+        let span = match self.program.statement_at(pc) {
+            Some(span) => span,
+            None => return false,
+        };
+        debugger.paused = debugger.breakpoints.contains(&span.line)
+            || match debugger.until {
+                Until::Breakpoint => false,
+                Until::Statement => true,
+                Until::Depth(max) => depth <= max,
+            };
+        debugger.paused
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("a frame is running")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("a frame is running")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("instruction inputs are on the stack")
+    }
+
+    fn pop_n(&mut self, count: u32) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count as usize)
+    }
+
+    /// Enters a chunk that sees the running frame's parameters, plus `slots`
+    fn enter(&mut self, chunk: Chunk, slots: &[Value]) {
+        let frame = self.frame();
+        let mut locals = frame.locals.clone();
+        locals.extend_from_slice(slots);
+        let frame = Frame {
+            pc: chunk.entry,
+            locals,
+            scope: chunk.scope,
+            depth: frame.depth,
+        };
+        self.frames.push(frame);
+    }
+
+    /// Takes the template's value for the last item of the innermost MAP,
+    /// FILTER or REDUCE, and runs it on the next, coming back here after.
+    /// Past the last item, reports what the items came to.
+    fn fold(&mut self) -> Result<(), LogoError> {
+        let value = if self.folds.last().expect("fold started").running {
+            Some(self.pop())
+        } else {
+            None
+        };
+        let fold = self.folds.last_mut().expect("fold started");
+        if let Some(value) = value {
+            match fold.kind {
+                Template::Map => fold.done.push(value),
+                Template::Filter => {
+                    if value.boolean()? {
+                        fold.done.push(fold.items[fold.next - 1].clone());
+                    }
                 }
-                _ => {}
+                _ => fold.done[0] = value,
             }
         }
+        let Some(item) = fold.items.get(fold.next).cloned() else {
+            let fold = self.folds.pop().expect("fold started");
+            self.stack.push(match fold.kind {
+                Template::Reduce => fold.done.into_iter().next().expect("reduce has a value"),
+                _ => Value::List(fold.done),
+            });
+            return Ok(());
+        };
+        fold.next += 1;
+        fold.running = true;
+        let chunk = fold.chunk;
+        let slots = match fold.kind {
+            Template::Reduce => vec![item, fold.done[0].clone()],
+            _ => vec![item],
+        };
+        // come back here for the template's value
+        self.frame_mut().pc -= 1;
+        self.enter(chunk, &slots);
         Ok(())
     }
 
+    fn call(&mut self, sym: Sym, values: Vec<Value>, tail: bool) -> Result<(), LogoError> {
+        let name = || self.program.name(sym).to_string();
+        let proc = self
+            .defined
+            .get(sym as usize)
+            .copied()
+            .flatten()
+            .ok_or_else(|| LogoError::UndefinedExpression(name()))?;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.called(proc);
+        }
+        let proc = &self.program.procs[proc as usize];
+        if proc.arity != values.len() {
+            return Err(LogoError::ArityMismatch {
+                name: name(),
+                expected: proc.arity,
+                got: values.len(),
+                span: None,
+            });
+        }
+        let mut frame = Frame {
+            pc: proc.entry,
+            locals: values,
+            scope: proc.scope,
+            depth: self.frame().depth,
+        };
+        // a tail call replaces the running call instead of nesting in it, so
+        // it is held back by the budget alone
+        if tail {
+            *self.frame_mut() = frame;
+            return Ok(());
+        }
+        frame.depth += 1;
+        if frame.depth > self.recursion_budget {
+            return Err(LogoError::RecursionBudgetExceeded(self.recursion_budget));
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn define(&mut self, proc: u32) {
+        let sym = self.program.procs[proc as usize].name as usize;
+        if self.defined.len() <= sym {
+            self.defined.resize(self.program.symbols(), None);
+        }
+        self.defined[sym] = Some(proc);
+        let name = self.program.name(sym as Sym).to_lowercase();
+        self.compiled
+            .retain(|_, compiled| !compiled.calls.contains(&name));
+    }
+
+    /// Signatures of the procedures defined so far, for code parsed at run time
+    fn signatures(&self) -> Signatures {
+        self.defined
+            .iter()
+            .enumerate()
+            .filter_map(|(sym, proc)| {
+                proc.map(|proc| {
+                    (
+                        self.program.name(sym as Sym).to_lowercase(),
+                        self.program.procs[proc as usize].arity,
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Compiles a RUN list to run with the running frame's parameters
+    fn compile_run(&mut self, code: String) -> Result<Chunk, LogoError> {
+        let scope = self.frame().scope;
+        let key = (code, scope, None);
+        if let Some(compiled) = self.compiled.get(&key) {
+            return Ok(compiled.chunk);
+        }
+        let (exps, warnings) = parse_with(&key.0, &self.signatures(), self.locale)?;
+        self.warnings.extend(warnings);
+        let calls = called(&key.0, self.locale)?;
+        let mark = self.make_room();
+        let entry = self.program.compile(&exps, scope);
+        self.program.unmark(&mark, entry);
+        let chunk = Chunk { entry, scope };
+        self.cache(key, mark, Compiled { chunk, calls });
+        Ok(chunk)
+    }
+
+    /// Compiles a template to run with the running frame's parameters, plus
+    /// its slots
+    fn compile_template(&mut self, template: &Value, kind: Template) -> Result<Chunk, LogoError> {
+        let parent = self.frame().scope;
+        let source = Self::template_source(template, &kind.inputs().join(" "));
+        let key = (source, parent, Some(kind));
+        if let Some(compiled) = self.compiled.get(&key) {
+            return Ok(compiled.chunk);
+        }
+        let mut slots = self.program.scopes[parent as usize].clone();
+        for slot in kind.slots() {
+            slots.push(self.program.intern(slot));
+        }
+        let scope = self.program.scope(slots);
+        // only statements are parsed knowing what's defined
+        let (exps, calls) = if kind.commands() {
+            let (exps, _) = parse_with(&key.0, &self.signatures(), self.locale)?;
+            (Some(exps), called(&key.0, self.locale)?)
+        } else {
+            (None, HashSet::new())
+        };
+        let mark = self.make_room();
+        let entry = match exps {
+            Some(exps) => self.program.compile(&exps, scope),
+            None => {
+                let exp = parse_expression(&key.0, self.locale)?;
+                self.program.compile_reporter(&exp, scope)
+            }
+        };
+        self.program.unmark(&mark, entry);
+        let chunk = Chunk { entry, scope };
+        self.cache(key, mark, Compiled { chunk, calls });
+        Ok(chunk)
+    }
+
+    /// Forgets the chunks parsed at run time that nothing is running, once
+    /// there are too many, and tells where the next one starts
+    fn make_room(&mut self) -> Mark {
+        if self.runtime.len() >= MAX_COMPILED {
+            let running = self
+                .frames
+                .iter()
+                .map(|frame| frame.pc)
+                .chain(self.templates.iter().map(|chunk| chunk.entry))
+                .chain(self.iterations.iter().map(|it| it.chunk.entry))
+                .chain(self.folds.iter().map(|fold| fold.chunk.entry))
+                .max()
+                .unwrap_or(0);
+            let keep = self.runtime.partition_point(|mark| mark.code <= running);
+            if let Some(mark) = self.runtime.get(keep).copied() {
+                self.program.truncate(&mark);
+                self.runtime.truncate(keep);
+                self.compiled
+                    .retain(|_, compiled| compiled.chunk.entry < mark.code);
+            }
+        }
+        self.program.mark()
+    }
+
+    /// Keeps a chunk compiled from `mark` on for reuse
+    fn cache(&mut self, key: CompiledKey, mark: Mark, compiled: Compiled) {
+        if self.program.declared_since(&mark) {
+            self.runtime.clear();
+        } else {
+            self.runtime.push(mark);
+        }
+        self.compiled.insert(key, compiled);
+    }
+
+    /// The code of a template: a list is code already, a word names a
+    /// procedure or primitive to use on `inputs`
+    fn template_source(template: &Value, inputs: &str) -> String {
+        match template {
+            Value::List(_) => template.printed(),
+            name => format!("{name} {inputs}"),
+        }
+    }
+
     #[allow(clippy::cast_possible_truncation)]
     #[allow(clippy::cast_sign_loss)]
-    fn eval_color(
-        &self,
-        r: &Expression,
-        g: &Expression,
-        b: &Expression,
-        args: &Option<HashMap<String, f32>>,
-    ) -> Result<(u8, u8, u8), LogoError> {
-        let r = self.eval_arg(r, args)? as u8;
-        let g = self.eval_arg(g, args)? as u8;
-        let b = self.eval_arg(b, args)? as u8;
-
-        Ok((r, g, b))
-    }
-
-    fn eval_condition(
-        &self,
-        condition: Expression,
-        args: &Option<HashMap<String, f32>>,
-    ) -> Result<bool, LogoError> {
-        Ok(match condition {
-            Expression::Condition(lhs, op, rhs) => {
-                let a = self.eval_arg(&lhs, args)?;
-                let b = self.eval_arg(&rhs, args)?;
-                match *op {
-                    Expression::Less => a < b,
-                    Expression::Gtr => a > b,
-                    Expression::Eq => Self::float_eq(a, b),
-                    Expression::Neq => !Self::float_eq(a, b),
-                    _ => false,
-                }
+    fn turtle_id(value: &Value) -> Result<usize, LogoError> {
+        let n = value.number()?;
+        if n < 0.0 || n.fract() != 0.0 {
+            return Err(LogoError::InvalidTurtle(value.to_string()));
+        }
+        Ok(n as usize)
+    }
+
+    /// Turtle ids given one by one, or as a single list value
+    fn turtle_ids(&mut self, count: u32) -> Result<Vec<usize>, LogoError> {
+        let values = self.pop_n(count);
+        if let [Value::List(ids)] = values.as_slice() {
+            return ids.iter().map(Self::turtle_id).collect();
+        }
+        values.iter().map(Self::turtle_id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logo::turtle::{Command, Shape, DEFAULT_SPEED};
+    use crate::logo::Expected;
+
+    /// Runs `code` on a 200 by 200 canvas, with what it printed or the error
+    fn run(code: &str) -> (Turtle, Result<Vec<String>, LogoError>) {
+        let mut turtle = Turtle::new((100.0, 100.0), 100_000);
+        turtle.canvas = Some((200.0, 200.0));
+        let mut exec = Executor::new();
+        let result = exec.run(&mut turtle, code).map(|()| exec.output());
+        (turtle, result)
+    }
+
+    fn lines(turtle: &Turtle) -> usize {
+        turtle
+            .commands
+            .iter()
+            .filter(|record| matches!(record.command, Command::Line(..)))
+            .count()
+    }
+
+    /// The example programs, with the output they are known to give: how
+    /// many commands, and an FNV-1a hash of the commands' `Debug` form
+    /// followed by the printed lines. The one-turtle examples still hash as
+    /// the tree-walking interpreter the VM replaced made them, so the VM is
+    /// held to its results. `turtles` was hashed anew once each turtle kept
+    /// its own time, and holds the VM to its own output since.
+    const EXAMPLES: &[(&str, &str, usize, u64)] = &[
+        (
+            "fern",
+            include_str!("testdata/fern.logo"),
+            32765,
+            0x7dce_1b0b_94af_02b9,
+        ),
+        (
+            "house",
+            include_str!("testdata/house.logo"),
+            56,
+            0xe4ea_ff56_d804_a30e,
+        ),
+        (
+            "snowflake",
+            include_str!("testdata/snowflake.logo"),
+            1537,
+            0x6feb_2fc2_8aa1_4a7f,
+        ),
+        (
+            "square",
+            include_str!("testdata/square.logo"),
+            9,
+            0xb7cb_41ee_d2ec_97c2,
+        ),
+        (
+            "tree",
+            include_str!("testdata/tree.logo"),
+            6392,
+            0x72ea_58f4_9485_d6c5,
+        ),
+        (
+            "turtles",
+            include_str!("testdata/turtles.logo"),
+            89,
+            0x924b_1833_366d_a3c4,
+        ),
+    ];
+
+    fn run_example(code: &str) -> (Turtle, Executor) {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000_000);
+        let mut exec = Executor::new();
+        exec.run(&mut turtle, code).expect("examples run");
+        (turtle, exec)
+    }
+
+    fn fnv(text: &str, hash: &mut u64) {
+        for byte in text.bytes() {
+            *hash ^= u64::from(byte);
+            *hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    #[test]
+    fn examples_give_their_golden_output() {
+        for (name, code, count, expected) in EXAMPLES {
+            let (turtle, exec) = run_example(code);
+            let mut hash = 0xcbf2_9ce4_8422_2325;
+            for record in &turtle.commands {
+                fnv(&format!("{record:?}\n"), &mut hash);
+            }
+            for line in exec.output() {
+                fnv(&format!("{line}\n"), &mut hash);
             }
-            _ => false,
-        })
+            assert_eq!(turtle.commands.len(), *count, "{name}");
+            assert_eq!(hash, *expected, "{name}");
+        }
+    }
+
+    /// Time per run of each example, for comparing changes to the VM
+    #[test]
+    #[ignore = "benchmark: cargo test --release -- --ignored --nocapture"]
+    fn bench_examples() {
+        for (name, code, _, _) in EXAMPLES {
+            let runs = 50;
+            let start = std::time::Instant::now();
+            for _ in 0..runs {
+                std::hint::black_box(run_example(code));
+            }
+            println!("{name}: {:?} a run", start.elapsed() / runs);
+        }
+    }
+
+    #[test]
+    fn code_parsed_at_run_time_is_kept_within_bounds() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000_000);
+        let mut exec = Executor::new();
+        let code = "repeat 2000 [run sentence \"fd repcount]";
+        exec.run(&mut turtle, code).unwrap();
+        assert!(exec.compiled.len() <= MAX_COMPILED);
+        assert!(exec.compiled.contains_key(&("fd 2000".into(), 0, None)));
+        assert!(!exec.compiled.contains_key(&("fd 1".into(), 0, None)));
+        // each `fd n` is three instructions
+        assert!(exec.program.code.len() < 3 * MAX_COMPILED + 20);
+        assert!(exec.program.consts.len() < MAX_COMPILED + 10);
+    }
+
+    #[test]
+    fn code_still_running_is_kept_when_the_rest_is_forgotten() {
+        let code = "run [repeat 300 [run sentence \"setpensize repcount] rt 90 fd 5]
+print map [? * 2] [1 2]";
+        let (turtle, output) = run(code);
+        assert_eq!(output.unwrap(), ["2 4"]);
+        assert_eq!(lines(&turtle), 1);
+        assert_eq!(turtle.turtles[&0].position, (105.0, 100.0));
+    }
+
+    #[test]
+    fn code_parsed_at_run_time_is_reused_until_what_it_calls_is_defined() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000_000);
+        let mut exec = Executor::new();
+        let code = "to f\nend\nrepeat 10 [run [f]]\nrepeat 10 [run [fd 1]]";
+        exec.run(&mut turtle, code).unwrap();
+        assert_eq!(exec.compiled.len(), 2);
+        exec.run(&mut turtle, "to g\nend").unwrap();
+        assert_eq!(exec.compiled.len(), 2);
+        exec.run(&mut turtle, "to F :x\nend").unwrap();
+        assert_eq!(exec.compiled.len(), 1);
+        assert!(exec.compiled.contains_key(&("fd 1".into(), 0, None)));
+    }
+
+    #[test]
+    fn programs_are_linted_when_asked() {
+        let code = "to unused\nend\nrun [repeat 0 [fd 1]]";
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.run(&mut turtle, code).unwrap();
+        assert!(exec.warnings().is_empty());
+        let mut exec = Executor::new();
+        exec.start_lint();
+        exec.run(&mut turtle, code).unwrap();
+        let warnings: Vec<_> = exec.warnings().into_iter().map(|w| w.message).collect();
+        // code RUN at run time isn't linted
+        assert_eq!(warnings, ["procedure `unused` is never called"]);
+    }
+
+    #[test]
+    fn lint_warnings_outlive_a_failed_run() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.start_lint();
+        let result = exec.run(&mut turtle, "fd :size");
+        assert!(result.is_err());
+        let warnings: Vec<_> = exec.warnings().into_iter().map(|w| w.message).collect();
+        assert_eq!(
+            warnings,
+            ["`:size` is used outside of any procedure with it as an input"]
+        );
+    }
+
+    #[test]
+    fn fence_fails_moves_off_the_canvas() {
+        let (turtle, result) = run("fence fd 50 fd 100");
+        assert!(matches!(result, Err(LogoError::OutOfBounds)));
+        assert_eq!(lines(&turtle), 1);
+        assert_eq!(turtle.turtles[&0].position, (100.0, 50.0));
+    }
+
+    #[test]
+    fn fence_fails_setpos_off_the_canvas() {
+        let (_, result) = run("fence setpos 10 250");
+        assert!(matches!(result, Err(LogoError::OutOfBounds)));
+        let (_, result) = run("fence setpos 10 190");
+        assert!(result.is_ok());
     }
 
-    fn float_eq(a: f32, b: f32) -> bool {
-        (a - b).abs() < 0.0001
+    #[test]
+    fn fence_keeps_to_the_canvas_wherever_home_is() {
+        let mut turtle = Turtle::new((0.0, 0.0), 100_000);
+        turtle.canvas = Some((300.0, 200.0));
+        let result = Executor::new().run(&mut turtle, "fence rt 90 fd 250 rt 90 fd 150");
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(lines(&turtle), 2);
+        let result = Executor::new().run(&mut turtle, "fd 100");
+        assert!(matches!(result, Err(LogoError::OutOfBounds)));
     }
 
-    pub fn eval_arg(
-        &self,
-        arg: &Expression,
-        args: &Option<HashMap<String, f32>>,
-    ) -> Result<f32, LogoError> {
-        match arg {
-            Expression::Number(n) => Ok(*n),
-            Expression::Var(id) => Self::eval_var(id, args),
-            Expression::Math(lhs, op, rhs) => self.eval_math(lhs, op, rhs, args),
-            exp => Err(LogoError::UndefinedExpression(format!("{exp:?}"))),
+    #[test]
+    fn window_lets_turtles_off_the_canvas() {
+        let (turtle, result) = run("fd 500 fence window bk 1000");
+        assert!(result.is_ok());
+        assert_eq!(lines(&turtle), 2);
+    }
+
+    #[test]
+    fn fence_errors_can_be_caught() {
+        let (turtle, result) = run("fence catch \"error [fd 500] print error");
+        assert_eq!(lines(&turtle), 0);
+        let printed = result.expect("the error is caught").join("\n");
+        assert!(printed.contains("turtle out of bounds"), "{printed}");
+    }
+
+    #[test]
+    fn repcount_is_minus_one_outside_of_repeat() {
+        let (_, result) = run("print repcount repeat 2 [print repcount]");
+        assert_eq!(result.unwrap(), ["-1", "1", "2"]);
+    }
+
+    #[test]
+    fn turtles_can_be_hidden_and_shown() {
+        let (turtle, result) = run("ht fd 10 st hideturtle showturtle");
+        result.unwrap();
+        let visible: Vec<bool> = turtle
+            .commands
+            .iter()
+            .filter_map(|record| match record.command {
+                Command::Visible(visible) => Some(visible),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(visible, [false, true, false, true]);
+    }
+
+    #[test]
+    fn shapes_may_be_polygons_of_at_least_three_points() {
+        let (turtle, result) = run("setshape [0 10 -5 0 5 0]");
+        result.unwrap();
+        let polygon = Shape::Polygon(vec![(0.0, 10.0), (-5.0, 0.0), (5.0, 0.0)]);
+        assert_eq!(turtle.turtles[&0].shape, polygon);
+        assert!(matches!(
+            &turtle.commands.last().unwrap().command,
+            Command::Shape(shape) if *shape == polygon
+        ));
+        for code in ["setshape [0 10 5 0]", "setshape [0 10 -5 0 5]"] {
+            let (_, result) = run(code);
+            assert!(matches!(
+                result,
+                Err(LogoError::SyntaxError {
+                    expected: Expected::Points,
+                    ..
+                })
+            ));
         }
     }
 
-    fn eval_var(id: &str, args: &Option<HashMap<String, f32>>) -> Result<f32, LogoError> {
-        args.as_ref().map_or_else(
-            || Err(LogoError::UndefinedParameter(id.to_string())),
-            |map| {
-                map.get(id).map_or_else(
-                    || Err(LogoError::UndefinedParameter(id.to_string())),
-                    |id| Ok(*id),
-                )
-            },
-        )
+    #[test]
+    fn stamp_imprints_the_turtle_as_it_is() {
+        let (turtle, result) = run("setcolor 255 0 0 setshape turtle rt 90 fd 20 stamp");
+        result.unwrap();
+        match &turtle.commands.last().unwrap().command {
+            Command::Stamp {
+                shape,
+                position,
+                heading,
+                color,
+            } => {
+                assert_eq!(*shape, Shape::Turtle);
+                assert_eq!(*position, (120.0, 100.0));
+                assert_eq!(*heading, 90.0);
+                assert_eq!(*color, Some((255, 0, 0)));
+            }
+            other => panic!("expected a stamp, got {other:?}"),
+        }
     }
 
-    pub fn eval_math(
-        &self,
-        lhs: &Expression,
-        op: &Expression,
-        rhs: &Expression,
-        args: &Option<HashMap<String, f32>>,
-    ) -> Result<f32, LogoError> {
-        let a = self.eval_arg(lhs, args)?;
-        let b = self.eval_arg(rhs, args)?;
-        match op {
-            Expression::Add => Ok(a + b),
-            Expression::Sub => Ok(a - b),
-            Expression::Mul => Ok(a * b),
-            Expression::Div => Ok(a / b),
-            exp => Err(LogoError::NoSuchOperator(format!("{exp:?}"))),
-        }
-    }
-    fn add_function(&mut self, ident: String, function: Function) {
-        self.functions.insert(ident, function);
-    }
-    fn call_function(
-        &mut self,
-        turtle: &mut Turtle,
-        ident: &str,
-        params: &[Expression],
-        args: &Option<HashMap<String, f32>>,
-        depth: usize,
-    ) -> Result<(), LogoError> {
-        let function = self
-            .functions
-            .get(ident)
-            .ok_or_else(|| LogoError::UndefinedExpression(ident.into()))?;
-        let exps = function.exps.clone();
-        let mut new_args: HashMap<String, f32> = HashMap::new();
-        for (exp, ident) in params.iter().zip(function.args.clone()) {
-            let n = self.eval_arg(&Box::new(exp.clone()), args)?;
-            new_args.insert(ident, n);
-        }
-        self.run_ast(exps, turtle, &Some(new_args), depth + 1)
+    #[test]
+    fn stamp_imprints_every_active_turtle() {
+        let (turtle, result) = run("tell [1 2] stamp");
+        result.unwrap();
+        let stamped: Vec<usize> = turtle
+            .commands
+            .iter()
+            .filter(|record| matches!(record.command, Command::Stamp { .. }))
+            .map(|record| record.turtle)
+            .collect();
+        assert_eq!(stamped, [1, 2]);
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct Function {
-    pub args: Vec<String>,
-    pub exps: Vec<Expression>,
-}
+    #[test]
+    fn each_turtle_keeps_its_own_state() {
+        let (turtle, result) = run("tell 1 pu fd 10 tell 0 rt 90 fd 20");
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].position, (120.0, 100.0));
+        assert_eq!(turtle.turtles[&0].angle, 90.0);
+        assert!(turtle.turtles[&0].pendown);
+        assert_eq!(turtle.turtles[&1].position, (100.0, 90.0));
+        assert_eq!(turtle.turtles[&1].angle, 0.0);
+        assert!(!turtle.turtles[&1].pendown);
+    }
+
+    #[test]
+    fn ask_goes_back_to_the_turtles_told_before() {
+        let (turtle, result) = run("tell [1 2] ask 3 [fd 10] fd 20");
+        result.unwrap();
+        assert_eq!(turtle.active, [1, 2]);
+        assert_eq!(turtle.turtles[&3].position, (100.0, 90.0));
+        assert_eq!(turtle.turtles[&2].position, (100.0, 80.0));
+        assert_eq!(turtle.turtles[&0].position, (100.0, 100.0));
+    }
+
+    #[test]
+    fn who_reports_the_turtles_listening() {
+        let (_, result) = run("print who tell [2 3] show who ask 5 [print who] print who");
+        assert_eq!(result.unwrap(), ["0", "[2 3]", "5", "2 3"]);
+    }
+
+    #[test]
+    fn turtle_ids_are_bounded() {
+        for code in ["tell 5000", "tell []", "ask [1 2000] [fd 1]"] {
+            let (_, result) = run(code);
+            assert!(matches!(result, Err(LogoError::InvalidTurtle(_))), "{code}");
+        }
+    }
+
+    #[test]
+    fn wait_holds_the_commands_after_it() {
+        let (turtle, result) = run("fd 10 wait 30 fd 20 wait -5 rt 90");
+        result.unwrap();
+        let timed: Vec<(usize, usize)> = turtle
+            .commands
+            .iter()
+            .skip(1)
+            .map(|record| (record.time, record.command.ticks(DEFAULT_SPEED)))
+            .collect();
+        assert_eq!(timed, [(0, 1), (1, 30), (31, 2), (33, 0), (33, 0)]);
+    }
+
+    #[test]
+    fn waits_are_in_the_stream_for_the_turtle_told() {
+        let (turtle, result) = run("tell 2 wait 10");
+        result.unwrap();
+        let last = turtle.commands.last().unwrap();
+        assert!(matches!(last.command, Command::Wait(ticks) if ticks == 10.0));
+        assert_eq!(last.turtle, 2);
+    }
+
+    #[test]
+    fn print_and_show_write_lines() {
+        let (_, result) = run("print [a [b c]] show [a [b c]] print \"word show 3 + 4");
+        assert_eq!(result.unwrap(), ["a [b c]", "[a [b c]]", "word", "7"]);
+    }
+
+    #[test]
+    fn type_keeps_the_line_going() {
+        let (_, result) = run("type \"a type [b c] print \"d type \"e");
+        assert_eq!(result.unwrap(), ["ab cd", "e"]);
+    }
+
+    #[test]
+    fn label_writes_on_the_drawing_not_the_output() {
+        let (turtle, result) = run("label [hello world]");
+        assert!(result.unwrap().is_empty());
+        assert!(matches!(
+            &turtle.commands.last().unwrap().command,
+            Command::Label(text) if text == "hello world"
+        ));
+    }
+
+    #[test]
+    fn run_sees_the_inputs_around_it() {
+        let (turtle, result) = run("to sq :n\nrepeat 4 [run [fd :n rt 90]]\nend\nsq 10");
+        result.unwrap();
+        assert_eq!(lines(&turtle), 4);
+        assert_eq!(turtle.turtles[&0].position, (100.0, 100.0));
+    }
+
+    #[test]
+    fn apply_calls_a_procedure_named_by_a_word() {
+        let code = "to step :n :turn\nfd :n rt :turn\nend\napply \"step [10 90] apply \"STEP [5 0]";
+        let (turtle, result) = run(code);
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].position, (105.0, 90.0));
+        let (_, result) = run("to step :n\nfd :n\nend\napply \"step [1 2]");
+        assert!(matches!(
+            result,
+            Err(LogoError::ArityMismatch {
+                expected: 1,
+                got: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn apply_runs_templates_and_primitives_on_inputs() {
+        let (turtle, result) = run("apply [fd ?1 rt ?2 fd ?] [10 90]");
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].position, (110.0, 90.0));
+        let (turtle, result) = run("apply \"setpos [10 20] apply \"fd [5]");
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].position, (10.0, 15.0));
+        let (_, output) = run("apply [print 1] []");
+        assert_eq!(output.unwrap(), ["1"]);
+        // a template only has slots for the inputs it was given
+        let (_, result) = run("apply [fd ?3] [1 2]");
+        assert!(matches!(result, Err(LogoError::UndefinedParameter(name)) if name == "?3"));
+        // its code is run, so it must be commands
+        let (_, result) = run("apply [? * 2] [3]");
+        assert!(matches!(
+            result,
+            Err(LogoError::SyntaxError {
+                expected: Expected::Statement,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn templates_map_filter_and_reduce_lists() {
+        let code = "show map [? * 2] [1 2 3]\nshow filter [? > 1] [1 2 3]\n\
+                    print reduce [?1 - ?2] [10 5 2]\nshow map [word ? \"!] [a b]";
+        let (_, result) = run(code);
+        assert_eq!(result.unwrap(), ["[2 4 6]", "[2 3]", "7", "[a! b!]"]);
+        let (_, result) = run("print reduce [?1 + ?2] []");
+        assert!(matches!(result, Err(LogoError::BadInput(..))));
+    }
+
+    #[test]
+    fn foreach_runs_a_template_on_each_item() {
+        let (turtle, result) = run("foreach [a b] [print ?] foreach [10 20] [fd ?]");
+        assert_eq!(result.unwrap(), ["a", "b"]);
+        assert_eq!(turtle.turtles[&0].position, (100.0, 70.0));
+    }
+
+    #[test]
+    fn repcount_counts_the_innermost_repeat() {
+        let (_, result) = run("repeat 2 [repeat 2 [type # type \"/] print repcount]");
+        assert_eq!(result.unwrap(), ["1/2/1", "1/2/2"]);
+    }
+
+    #[test]
+    fn repcount_reaches_into_procedures_called_from_repeat() {
+        let (turtle, result) = run("to side\nfd repcount * 10\nend\nrepeat 3 [side]");
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].position, (100.0, 40.0));
+    }
+
+    #[test]
+    fn the_last_definition_of_a_procedure_is_the_one_called() {
+        let code = "to step\nfd 10\nend\nstep\nto step\nfd 20\nend\nstep";
+        let (turtle, result) = run(code);
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].position, (100.0, 60.0));
+    }
+
+    #[test]
+    fn procedures_named_like_a_primitive_are_called_instead() {
+        let (turtle, result) = run("to fd :n\nrt :n\nend\nfd 90");
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].angle, 90.0);
+        assert_eq!(lines(&turtle), 0);
+    }
+
+    #[test]
+    fn tail_calls_recurse_past_the_recursion_budget() {
+        let code = "to walk :n\nif :n > 0 [fd 1 walk :n - 1]\nend\nwalk 3000";
+        let (turtle, result) = run(code);
+        result.unwrap();
+        assert_eq!(lines(&turtle), 3000);
+        // with no end to it, the run-time budget stops it instead
+        let (_, result) = run("to spin\nrt 1\nspin\nend\nspin");
+        assert!(matches!(result, Err(LogoError::RuntimeBudgetExceeded(_))));
+    }
+
+    #[test]
+    fn every_instruction_counts_against_the_budget() {
+        let (_, result) = run("to count :n\ncount :n + 1\nend\ncount 0");
+        assert!(matches!(
+            result,
+            Err(LogoError::RuntimeBudgetExceeded(100_000))
+        ));
+        let (_, result) = run("repeat 1000000000 []");
+        assert!(matches!(
+            result,
+            Err(LogoError::RuntimeBudgetExceeded(100_000))
+        ));
+        // the output stops growing with the run
+        let mut turtle = Turtle::new((100.0, 100.0), 100_000);
+        let mut exec = Executor::new();
+        let result = exec.run(&mut turtle, "repeat 1000000000 [print 1]");
+        assert!(matches!(
+            result,
+            Err(LogoError::RuntimeBudgetExceeded(100_000))
+        ));
+        assert!(exec.output().len() < 100_000);
+        // templates count too
+        let (_, result) = run("show map [? + 1] [1 2 3]\nfd 1");
+        result.unwrap();
+        let mut turtle = Turtle::new((100.0, 100.0), 10);
+        let result = Executor::new().run(&mut turtle, "show map [? + 1] [1 2 3 4 5]");
+        assert!(matches!(result, Err(LogoError::RuntimeBudgetExceeded(10))));
+    }
+
+    #[test]
+    fn calls_with_more_to_do_after_them_count_against_the_budget() {
+        let code = "to deep :n\nif :n > 0 [deep :n - 1 fd 1]\nend\ndeep 3000";
+        let (_, result) = run(code);
+        assert!(matches!(
+            result,
+            Err(LogoError::RecursionBudgetExceeded(1000))
+        ));
+        let (turtle, result) = run("to deep :n\nif :n > 0 [deep :n - 1 fd 1]\nend\ndeep 500");
+        result.unwrap();
+        assert_eq!(lines(&turtle), 500);
+    }
+
+    #[test]
+    fn slices_add_up_to_the_whole_run() {
+        for (name, code, ..) in EXAMPLES {
+            let (whole, exec) = run_example(code);
+            let mut turtle = Turtle::new((0.0, 0.0), 1_000_000);
+            let mut sliced = Executor::new();
+            sliced.load(code).unwrap();
+            let mut slices = 0;
+            while !sliced.step(&mut turtle, 7).unwrap() {
+                slices += 1;
+            }
+            assert!(slices > 1, "{name}");
+            assert_eq!(
+                format!("{:?}", turtle.commands),
+                format!("{:?}", whole.commands),
+                "{name}"
+            );
+            assert_eq!(sliced.output(), exec.output(), "{name}");
+        }
+    }
+
+    #[test]
+    fn a_large_map_runs_across_slices() {
+        let items: Vec<String> = (1..=1000).map(|n| n.to_string()).collect();
+        let code = format!("show count map [? * 2] [{}]", items.join(" "));
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000_000);
+        let mut exec = Executor::new();
+        exec.load(&code).unwrap();
+        let mut slices = 1;
+        while !exec.step(&mut turtle, 100).unwrap() {
+            slices += 1;
+        }
+        // a thousand items take a few instructions each
+        assert!(slices > 30, "{slices}");
+        assert_eq!(exec.output(), ["1000"]);
+    }
+
+    #[test]
+    fn errors_in_templates_leave_nothing_half_done() {
+        let (_, result) = run("catch \"error [show filter [?] [1 2]]\nshow map [? + 1] [1 2]");
+        assert_eq!(result.unwrap(), ["[2 3]"]);
+        let (_, result) = run("catch \"error [show reduce [?1 + first []] [1 2 3]]\nshow 4");
+        assert_eq!(result.unwrap(), ["4"]);
+    }
+
+    #[test]
+    fn steps_go_on_from_where_the_last_one_stopped() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.load("repeat 3 [fd 10]\nprint first []").unwrap();
+        assert!(!exec.step(&mut turtle, 0).unwrap());
+        assert_eq!(lines(&turtle), 0);
+        let mut drawn = vec![];
+        // until FIRST fails
+        while let Ok(done) = exec.step(&mut turtle, 1) {
+            assert!(!done);
+            drawn.push(lines(&turtle));
+        }
+        // one instruction at a time, lines come one by one
+        assert!(drawn.windows(2).all(|pair| pair[1] - pair[0] <= 1));
+        assert_eq!(drawn.last(), Some(&3));
+    }
+
+    const SQUARE: &str = "to sq :side\nrepeat 4 [\n  fd :side\n  rt 90]\nend\nsq 10\nprint \"done";
+
+    /// Where a paused program is: the line and procedure of its innermost
+    /// frame, and how deep the stack is
+    fn paused_at(exec: &Executor, turtle: &Turtle) -> (usize, Option<String>, usize) {
+        let snapshot = exec.snapshot(turtle);
+        let top = snapshot.frames.last().expect("a frame is running");
+        let line = top.span.as_ref().expect("statements have spans").line;
+        (line, top.procedure.clone(), snapshot.frames.len())
+    }
+
+    #[test]
+    fn breakpoints_pause_before_their_line_each_time() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.load(SQUARE).unwrap();
+        exec.set_breakpoints(&[3]);
+        for drawn in 0..4 {
+            assert!(!exec.step(&mut turtle, usize::MAX).unwrap());
+            assert!(exec.paused());
+            assert_eq!(paused_at(&exec, &turtle), (3, Some("sq".into()), 2));
+            assert_eq!(lines(&turtle), drawn);
+            exec.resume(Resume::Continue);
+        }
+        assert!(exec.step(&mut turtle, usize::MAX).unwrap());
+        assert_eq!(exec.output(), ["done"]);
+    }
+
+    #[test]
+    fn snapshots_show_inputs_and_the_turtle() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.load(SQUARE).unwrap();
+        exec.set_breakpoints(&[4]);
+        exec.step(&mut turtle, usize::MAX).unwrap();
+        let snapshot = exec.snapshot(&turtle);
+        let top = snapshot.frames.last().unwrap();
+        assert_eq!(top.args.get(":side").map(String::as_str), Some("10"));
+        assert_eq!(snapshot.frames[0].procedure, None);
+        assert_eq!(snapshot.frames[0].span.as_ref().unwrap().line, 6);
+        assert_eq!(snapshot.turtle, 0);
+        assert_eq!(snapshot.state.position, (0.0, -10.0));
+        assert!(matches!(
+            snapshot.last.map(|record| record.command),
+            Some(Command::Line(..))
+        ));
+    }
+
+    #[test]
+    fn stepping_over_into_and_out_of_calls() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.load(SQUARE).unwrap();
+        exec.set_breakpoints(&[6]);
+        exec.step(&mut turtle, usize::MAX).unwrap();
+        assert_eq!(paused_at(&exec, &turtle), (6, None, 1));
+        exec.resume(Resume::StepInto);
+        exec.step(&mut turtle, usize::MAX).unwrap();
+        assert_eq!(paused_at(&exec, &turtle), (2, Some("sq".into()), 2));
+        exec.resume(Resume::StepOut);
+        exec.step(&mut turtle, usize::MAX).unwrap();
+        assert_eq!(paused_at(&exec, &turtle), (7, None, 1));
+        assert_eq!(lines(&turtle), 4);
+
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.load(SQUARE).unwrap();
+        exec.set_breakpoints(&[6]);
+        exec.step(&mut turtle, usize::MAX).unwrap();
+        exec.resume(Resume::StepOver);
+        exec.step(&mut turtle, usize::MAX).unwrap();
+        assert_eq!(paused_at(&exec, &turtle), (7, None, 1));
+        assert_eq!(lines(&turtle), 4);
+    }
+
+    #[test]
+    fn traces_point_each_command_at_its_statement_and_calls() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        turtle.start_trace();
+        let mut exec = Executor::new();
+        exec.run(&mut turtle, &format!("setcolor 1 2 3\n{SQUARE}"))
+            .unwrap();
+        let trace = turtle.trace.as_ref().unwrap();
+        assert_eq!(trace.len(), turtle.commands.len());
+        assert!(trace[0].span.is_none());
+        assert_eq!(trace[1].span.as_ref().unwrap().line, 1);
+        assert!(trace[1].calls.is_empty());
+        for (record, origin) in turtle.commands.iter().zip(trace).skip(2) {
+            let line = origin.span.as_ref().unwrap().line;
+            match record.command {
+                Command::Line(..) => assert_eq!(line, 4),
+                Command::Heading(_) => assert_eq!(line, 5),
+                ref other => panic!("unexpected {other:?}"),
+            }
+            let calls: Vec<(&str, usize)> = origin
+                .calls
+                .iter()
+                .map(|call| (call.procedure.as_str(), call.span.as_ref().unwrap().line))
+                .collect();
+            assert_eq!(calls, [("sq", 7)]);
+        }
+    }
+
+    #[test]
+    fn untraced_runs_keep_no_trace() {
+        let (turtle, result) = run(SQUARE);
+        result.unwrap();
+        assert!(turtle.trace.is_none());
+    }
+
+    #[test]
+    fn profiles_count_work_by_procedure_and_line() {
+        let code = "to leaf\nfd 1\nend\nto branch\nrepeat 3 [leaf]\nrt 90\nend\nrepeat 2 [branch]";
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        assert!(exec.profile().is_none());
+        exec.start_profile();
+        exec.run(&mut turtle, code).unwrap();
+        let profile = exec.profile().unwrap();
+        assert_eq!((profile.total.calls, profile.total.commands), (8, 8));
+        let procedures: Vec<(&str, usize, usize)> = profile
+            .procedures
+            .iter()
+            .map(|proc| (proc.name.as_str(), proc.counts.calls, proc.counts.commands))
+            .collect();
+        // busiest first: BRANCH runs more instructions, looping
+        assert_eq!(procedures, [("branch", 2, 2), ("leaf", 6, 6)]);
+        assert!(profile.procedures[0].counts.steps >= profile.procedures[1].counts.steps);
+        let lines: Vec<(usize, usize, usize)> = profile
+            .lines
+            .iter()
+            .map(|line| (line.line, line.counts.calls, line.counts.commands))
+            .collect();
+        assert_eq!(lines, [(2, 0, 6), (5, 6, 0), (6, 0, 2), (8, 2, 0)]);
+        let steps: usize = profile.lines.iter().map(|line| line.counts.steps).sum();
+        assert!(steps <= profile.total.steps);
+    }
+
+    #[test]
+    fn profiles_count_the_work_of_templates() {
+        let mut turtle = Turtle::new((0.0, 0.0), 1_000);
+        let mut exec = Executor::new();
+        exec.start_profile();
+        exec.run(&mut turtle, "show map [? * 2] [1 2 3 4 5 6 7 8 9 10]")
+            .unwrap();
+        let profile = exec.profile().unwrap();
+        // CONST TEMPLATE CONST MAP FOLD SHOW RETURN, FOLD again after each
+        // item, and LOAD TONUMBER NUMBER MUL RETURN for each item, all of it
+        // on the line that maps
+        assert_eq!(profile.total.steps, 7 + 10 + 10 * 5);
+        assert_eq!(profile.lines[0].counts.steps, profile.total.steps);
+    }
+
+    #[test]
+    fn throw_goes_on_after_its_catch() {
+        let (turtle, result) = run("catch \"done [fd 10 throw \"done fd 10] fd 5");
+        result.unwrap();
+        assert_eq!(turtle.turtles[&0].position, (100.0, 85.0));
+        let (turtle, result) = run("catch \"x [ask 1 [repeat 3 [throw \"x]]] fd 10");
+        result.unwrap();
+        assert_eq!(turtle.active, [0]);
+        assert_eq!(turtle.turtles[&0].position, (100.0, 90.0));
+    }
+
+    #[test]
+    fn throws_nobody_catches_are_errors() {
+        let (_, result) = run("catch \"a [throw \"b]");
+        assert!(matches!(result, Err(LogoError::Throw(tag, None)) if tag == "b"));
+        let (_, result) = run("throw \"error [out of ink]");
+        let err = result.unwrap_err();
+        assert!(matches!(err, LogoError::UserError(_)));
+        assert_eq!(err.to_string(), "out of ink");
+    }
+
+    #[test]
+    fn error_reports_the_message_procedure_and_line_caught() {
+        let code = "to f\nprint first []\nend\n\
+                    to report :e\nprint first :e print item 2 :e print last :e\nend\n\
+                    catch \"error [f]\nreport error";
+        let (_, result) = run(code);
+        assert_eq!(
+            result.unwrap(),
+            ["`first` doesn't like `[]` as input", "f", "2"]
+        );
+        let (_, result) = run("catch \"error [throw \"error \"oops]\nshow error show error");
+        // reading it clears it
+        assert_eq!(result.unwrap(), ["[oops [] 1]", "[]"]);
+    }
 
-impl Function {
-    fn new(args: Vec<String>, exps: Vec<Expression>) -> Self {
-        Self { args, exps }
+    #[test]
+    fn running_out_of_budget_cannot_be_caught() {
+        let (_, result) = run("catch \"error [repeat 200000 [rt 1]]");
+        assert!(matches!(result, Err(LogoError::RuntimeBudgetExceeded(_))));
     }
 }
@@ -0,0 +1,472 @@
+// Prints programs back as canonical Logo: a statement a line, keywords in
+// uppercase and blocks indented, with comments kept where they were.
+//
+// Blocks that fit on a line and hold no blocks or comments of their own stay
+// on one line, as in `REPEAT 4 [FORWARD 10 RIGHT 90]`. Blank lines between
+// statements are kept, a single one for a run of them. List literals are
+// data, so they are printed as the program sees them.
+//
+use super::locale::Locale;
+use super::parser::{self, Expression, Keyword, Signatures, Span};
+use super::turtle::Shape;
+use super::value::Value;
+use super::LogoError;
+use std::cmp::Reverse;
+use std::collections::{HashSet, VecDeque};
+
+/// How keywords that have a short alias are spelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aliases {
+    /// `FORWARD`
+    Expand,
+    /// `FD`
+    Contract,
+}
+
+const INDENT: &str = "  ";
+
+/// Longest line a block is kept on, indentation included
+const WIDTH: usize = 80;
+
+/// Formats `code`, written with `locale`'s keywords, which it keeps using
+/// where the locale has a spelling for them
+pub fn format(code: &str, locale: &Locale, aliases: Aliases) -> Result<String, LogoError> {
+    let (exps, _) = parser::parse_with(code, &Signatures::new(), locale)?;
+    let mut procs = HashSet::new();
+    procedures(&exps, &mut procs);
+    let mut printer = Printer {
+        code,
+        locale,
+        aliases,
+        procs,
+        comments: parser::comments(code)?.into(),
+        lines: vec![],
+        last: 0,
+        opened: false,
+    };
+    printer.block(&exps, code.len(), 0);
+    let mut formatted = printer.lines.join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+/// Lowercased names of the procedures defined anywhere in `exps`
+fn procedures(exps: &[Expression], procs: &mut HashSet<String>) {
+    for exp in exps {
+        match exp {
+            Expression::To(name, _, body) => {
+                procs.insert(name.to_lowercase());
+                procedures(body, procs);
+            }
+            Expression::Repeat(_, body)
+            | Expression::If(_, body)
+            | Expression::Ask(_, body)
+            | Expression::Catch(_, body) => procedures(body, procs),
+            _ => {}
+        }
+    }
+}
+
+/// The statements of a block, each with where it is in the source
+fn statements(exps: &[Expression]) -> Vec<(&Span, &Expression)> {
+    let mut statements = vec![];
+    let mut exps = exps.iter();
+    while let Some(exp) = exps.next() {
+        if let (Expression::Source(span), Some(statement)) = (exp, exps.next()) {
+            statements.push((span, statement));
+        }
+    }
+    statements
+}
+
+struct Printer<'a> {
+    code: &'a str,
+    locale: &'a Locale,
+    aliases: Aliases,
+    /// Procedures a keyword must not be spelled like, or it would call them
+    procs: HashSet<String>,
+    /// Comments not printed yet
+    comments: VecDeque<Span>,
+    lines: Vec<String>,
+    /// The source line what was printed last ends on
+    last: usize,
+    /// A block was just opened, so no blank line goes before what follows
+    opened: bool,
+}
+
+impl Printer<'_> {
+    /// Prints the statements of a block, and the comments in it up to `limit`
+    fn block(&mut self, exps: &[Expression], limit: usize, depth: usize) {
+        let statements = statements(exps);
+        for (i, (span, exp)) in statements.iter().enumerate() {
+            self.comments_before(span.start, depth);
+            self.gap(span.line);
+            self.statement(exp, span, depth);
+            // a comment after the statement, on the line it ends on
+            let next = statements.get(i + 1).map_or(limit, |(span, _)| span.start);
+            self.trailing_comment(self.last, next);
+        }
+        self.comments_before(limit, depth);
+    }
+
+    /// Ends the line just printed with the next comment, if it is on source
+    /// line `line` and before `limit`
+    fn trailing_comment(&mut self, line: usize, limit: usize) {
+        if matches!(self.comments.front(), Some(c) if c.start < limit && c.line == line) {
+            let comment = self.comments.pop_front().expect("comment was just seen");
+            let text = self.code[comment.start..comment.end].trim_end().to_string();
+            if let Some(line) = self.lines.last_mut() {
+                line.push(' ');
+                line.push_str(&text);
+            }
+            self.last = self.end_line(&comment);
+        }
+    }
+
+    /// Opens a block with its header, which keeps a comment that followed it
+    /// on its line
+    fn open(&mut self, header: String, span: &Span, body: &[Expression], depth: usize) {
+        self.line(depth, header);
+        self.last = span.line;
+        let first = statements(body)
+            .first()
+            .map_or(span.end, |(span, _)| span.start);
+        self.trailing_comment(span.line, first);
+        self.opened = true;
+    }
+
+    fn statement(&mut self, exp: &Expression, span: &Span, depth: usize) {
+        let (head, body) = match exp {
+            Expression::To(name, params, body) => {
+                let mut head = vec![self.keyword("to"), name.clone()];
+                head.extend(params.iter().cloned());
+                self.open(head.join(" "), span, body, depth);
+                self.block(body, span.end, depth + 1);
+                self.line(depth, self.keyword("end"));
+                self.last = self.end_line(span);
+                return;
+            }
+            Expression::Repeat(count, body) => (
+                format!("{} {}", self.keyword("repeat"), self.exp(count)),
+                body,
+            ),
+            Expression::If(condition, body) => (
+                format!("{} {}", self.keyword("if"), self.exp(condition)),
+                body,
+            ),
+            Expression::Ask(ids, body) => (
+                format!("{} {}", self.keyword("ask"), self.turtles(ids)),
+                body,
+            ),
+            Expression::Catch(tag, body) => {
+                (format!("{} {}", self.keyword("catch"), self.exp(tag)), body)
+            }
+            _ => {
+                self.line(depth, self.exp(exp));
+                self.last = self.end_line(span);
+                return;
+            }
+        };
+        let commented = matches!(self.comments.front(), Some(c) if c.start < span.end);
+        if let Some(inline) = self.inline(body).filter(|_| !commented) {
+            let text = format!("{head} [{inline}]");
+            if INDENT.len() * depth + text.chars().count() <= WIDTH {
+                self.line(depth, text);
+                self.last = self.end_line(span);
+                return;
+            }
+        }
+        self.open(format!("{head} ["), span, body, depth);
+        self.block(body, span.end, depth + 1);
+        self.line(depth, "]".into());
+        self.last = self.end_line(span);
+    }
+
+    /// A block on one line, unless it holds blocks of its own
+    fn inline(&self, body: &[Expression]) -> Option<String> {
+        let mut parts = vec![];
+        for (_, exp) in statements(body) {
+            if matches!(
+                exp,
+                Expression::To(..)
+                    | Expression::Repeat(..)
+                    | Expression::If(..)
+                    | Expression::Ask(..)
+                    | Expression::Catch(..)
+            ) {
+                return None;
+            }
+            parts.push(self.exp(exp));
+        }
+        Some(parts.join(" "))
+    }
+
+    fn comments_before(&mut self, limit: usize, depth: usize) {
+        while matches!(self.comments.front(), Some(c) if c.start < limit) {
+            let comment = self.comments.pop_front().expect("comment was just seen");
+            self.gap(comment.line);
+            let text = self.code[comment.start..comment.end].trim_end().to_string();
+            self.line(depth, text);
+            self.last = self.end_line(&comment);
+        }
+    }
+
+    /// Keeps a blank line before what starts on `line`, if the source has one
+    fn gap(&mut self, line: usize) {
+        if !self.opened && !self.lines.is_empty() && line > self.last + 1 {
+            self.lines.push(String::new());
+        }
+    }
+
+    fn line(&mut self, depth: usize, text: String) {
+        self.lines.push(INDENT.repeat(depth) + &text);
+        self.opened = false;
+    }
+
+    fn end_line(&self, span: &Span) -> usize {
+        span.line + self.code[span.start..span.end].matches('\n').count()
+    }
+
+    /// How a keyword is spelled: in the locale's language when it can be,
+    /// long or short as asked, and never like a procedure of the program
+    fn keyword(&self, name: &str) -> String {
+        let mut local: Vec<&str> = self.locale.spellings(name).collect();
+        let mut english: Vec<&str> = std::iter::once(name)
+            .chain(
+                Keyword::named(name)
+                    .map_or(&[][..], |keyword| keyword.aliases)
+                    .iter()
+                    .copied(),
+            )
+            .collect();
+        for spellings in [&mut local, &mut english] {
+            match self.aliases {
+                Aliases::Expand => spellings.sort_by_key(|s| Reverse(s.chars().count())),
+                Aliases::Contract => spellings.sort_by_key(|s| s.chars().count()),
+            }
+        }
+        local
+            .into_iter()
+            .chain(english)
+            .find(|spelling| !self.procs.contains(&spelling.to_lowercase()))
+            .unwrap_or(name)
+            .to_uppercase()
+    }
+
+    /// A keyword followed by its inputs
+    fn command(&self, name: &str, inputs: &[&Expression]) -> String {
+        std::iter::once(self.keyword(name))
+            .chain(inputs.iter().map(|input| self.exp(input)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A single turtle id, or a bracketed list of them
+    fn turtles(&self, ids: &[Expression]) -> String {
+        match ids {
+            [id] if !matches!(id, Expression::List(_)) => self.exp(id),
+            _ => format!(
+                "[{}]",
+                ids.iter()
+                    .map(|id| self.exp(id))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        }
+    }
+
+    fn exp(&self, exp: &Expression) -> String {
+        match exp {
+            Expression::Clear => self.keyword("clear"),
+            Expression::Penup => self.keyword("penup"),
+            Expression::Pendown => self.keyword("pendown"),
+            Expression::Showturtle => self.keyword("showturtle"),
+            Expression::Hideturtle => self.keyword("hideturtle"),
+            Expression::Stamp => self.keyword("stamp"),
+            Expression::Fence => self.keyword("fence"),
+            Expression::Window => self.keyword("window"),
+            Expression::Who => self.keyword("who"),
+            Expression::Repcount => self.keyword("repcount"),
+            Expression::Error => self.keyword("error"),
+            Expression::Setpos(x, y) => self.command("setpos", &[x, y]),
+            Expression::Setcolor(r, g, b) => self.command("setcolor", &[r, g, b]),
+            Expression::Setpensize(x) => self.command("setpensize", &[x]),
+            Expression::Setshape(shape) => {
+                let shape = match shape {
+                    Shape::Triangle => "triangle".into(),
+                    Shape::Turtle => "turtle".into(),
+                    Shape::Arrow => "arrow".into(),
+                    Shape::Circle => self.keyword("circle"),
+                    Shape::Polygon(points) => Value::List(
+                        points
+                            .iter()
+                            .flat_map(|(x, y)| [Value::Number(*x), Value::Number(*y)])
+                            .collect(),
+                    )
+                    .to_string(),
+                };
+                format!("{} {shape}", self.keyword("setshape"))
+            }
+            Expression::Tell(ids) => format!("{} {}", self.keyword("tell"), self.turtles(ids)),
+            Expression::Setturtle(x) => self.command("setturtle", &[x]),
+            Expression::Setspeed(x) => self.command("setspeed", &[x]),
+            Expression::Wait(x) => self.command("wait", &[x]),
+            Expression::Label(x) => self.command("label", &[x]),
+            Expression::Print(x) => self.command("print", &[x]),
+            Expression::Show(x) => self.command("show", &[x]),
+            Expression::Type(x) => self.command("type", &[x]),
+            Expression::Run(x) => self.command("run", &[x]),
+            Expression::Apply(f, x) => self.command("apply", &[f, x]),
+            Expression::Foreach(xs, f) => self.command("foreach", &[xs, f]),
+            Expression::Throw(tag, None) => self.command("throw", &[tag]),
+            Expression::Throw(tag, Some(value)) => self.command("throw", &[tag, value]),
+            Expression::Forward(x) => self.command("forward", &[x]),
+            Expression::Back(x) => self.command("back", &[x]),
+            Expression::Right(x) => self.command("right", &[x]),
+            Expression::Left(x) => self.command("left", &[x]),
+            Expression::Arc(angle, radius) => self.command("arc", &[angle, radius]),
+            Expression::Circle(x) => self.command("circle", &[x]),
+            Expression::Map(f, xs) => self.command("map", &[f, xs]),
+            Expression::Filter(f, xs) => self.command("filter", &[f, xs]),
+            Expression::Reduce(f, xs) => self.command("reduce", &[f, xs]),
+            Expression::Primitive(p, inputs) => {
+                self.command(p.name(), &inputs.iter().collect::<Vec<_>>())
+            }
+            // names keep the case they were written in
+            Expression::Call(name, args) => std::iter::once(name.clone())
+                .chain(args.iter().map(|arg| self.exp(arg)))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Expression::Number(n) => n.to_string(),
+            Expression::Var(x) => x.clone(),
+            Expression::Word(x) => format!("\"{x}"),
+            Expression::List(items) => Value::List(items.clone()).to_string(),
+            Expression::Condition(lhs, op, rhs) | Expression::Math(lhs, op, rhs) => {
+                format!("{} {} {}", self.exp(lhs), self.exp(op), self.exp(rhs))
+            }
+            Expression::Less => "<".into(),
+            Expression::Gtr => ">".into(),
+            Expression::Eq => "==".into(),
+            Expression::Neq => "!=".into(),
+            Expression::Add => "+".into(),
+            Expression::Sub => "-".into(),
+            Expression::Mul => "*".into(),
+            Expression::Div => "/".into(),
+            // blocks are laid out by `statement`
+            Expression::To(..)
+            | Expression::Repeat(..)
+            | Expression::If(..)
+            | Expression::Ask(..)
+            | Expression::Catch(..)
+            | Expression::Source(_) => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logo::executor::Executor;
+    use crate::logo::locale::{ENGLISH, HEBREW};
+    use crate::logo::turtle::Turtle;
+
+    #[test]
+    fn lists_are_printed_as_written() {
+        let formatted = format("print [I go FD and cs]\n", &ENGLISH, Aliases::Expand);
+        assert_eq!(formatted.unwrap(), "PRINT [I go FD and cs]\n");
+        let formatted = format("הדפס [שלום קד]\n", &HEBREW, Aliases::Expand);
+        assert_eq!(formatted.unwrap(), "הדפס [שלום קד]\n");
+    }
+
+    const EXAMPLES: &[&str] = &[
+        include_str!("testdata/fern.logo"),
+        include_str!("testdata/house.logo"),
+        include_str!("testdata/snowflake.logo"),
+        include_str!("testdata/square.logo"),
+        include_str!("testdata/tree.logo"),
+        include_str!("testdata/turtles.logo"),
+        "; hi\nfd 10 ; go\n\n\n\nrt 90\nrepeat 2 [fd 10 ; inner\nrt 90]\nif 1 < 2 [print \"yes]",
+        "to fd :n\nforward :n\nend\nfd 5",
+        "to sq :n ; a square\nrepeat 4 [ ; a side\nfd :n rt 90]\nif :n > 5 [ ; smaller\nsq :n / 2]\nend\nsq 10",
+    ];
+
+    fn formatted(code: &str, aliases: Aliases) -> String {
+        format(code, &ENGLISH, aliases).expect("code must parse")
+    }
+
+    #[test]
+    fn a_statement_a_line_and_blocks_indented() {
+        assert_eq!(
+            formatted("to sq :n repeat 4 [fd :n rt 90] end sq 10", Aliases::Expand),
+            "TO sq :n\n  REPEAT 4 [FORWARD :n RIGHT 90]\nEND\nsq 10\n"
+        );
+        // too long for a line with the keywords spelled out
+        let long =
+            "repeat 2 [fd 100 rt 120 fd 100 rt 120 fd 100 rt 120 fd 100 rt 120 fd 100 rt 90]";
+        assert_eq!(
+            formatted(long, Aliases::Expand),
+            "REPEAT 2 [\n  FORWARD 100\n  RIGHT 120\n  FORWARD 100\n  RIGHT 120\n  \
+             FORWARD 100\n  RIGHT 120\n  FORWARD 100\n  RIGHT 120\n  FORWARD 100\n  \
+             RIGHT 90\n]\n"
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        assert_eq!(
+            formatted(EXAMPLES[6], Aliases::Contract),
+            "; hi\nFD 10 ; go\n\nRT 90\nRP 2 [\n  FD 10 ; inner\n  RT 90\n]\nIF 1 < 2 [PR \"yes]\n"
+        );
+    }
+
+    #[test]
+    fn comments_after_a_header_stay_on_its_line() {
+        assert_eq!(
+            formatted(EXAMPLES[8], Aliases::Contract),
+            "TO sq :n ; a square\n  RP 4 [ ; a side\n    FD :n\n    RT 90\n  ]\n  \
+             IF :n > 5 [ ; smaller\n    sq :n / 2\n  ]\nEND\nsq 10\n"
+        );
+    }
+
+    #[test]
+    fn keywords_are_not_spelled_like_a_procedure() {
+        assert_eq!(
+            formatted(EXAMPLES[7], Aliases::Contract),
+            "TO fd :n\n  FORWARD :n\nEND\nfd 5\n"
+        );
+    }
+
+    #[test]
+    fn formatting_again_changes_nothing() {
+        for code in EXAMPLES {
+            for aliases in [Aliases::Expand, Aliases::Contract] {
+                let once = formatted(code, aliases);
+                assert_eq!(formatted(&once, aliases), once, "{code}");
+            }
+        }
+        let once = format(
+            "למד ריבוע\nחזור 4 [קד 10 ימ 90]\nסוף",
+            &HEBREW,
+            Aliases::Expand,
+        )
+        .unwrap();
+        assert_eq!(format(&once, &HEBREW, Aliases::Expand).unwrap(), once);
+    }
+
+    #[test]
+    fn formatted_programs_draw_the_same() {
+        let drawn = |code: &str| {
+            let mut turtle = Turtle::new((0.0, 0.0), 1_000_000);
+            let mut exec = Executor::new();
+            exec.run(&mut turtle, code).expect("examples run");
+            format!("{:?} {:?}", turtle.commands, exec.output())
+        };
+        for code in EXAMPLES {
+            for aliases in [Aliases::Expand, Aliases::Contract] {
+                assert_eq!(drawn(&formatted(code, aliases)), drawn(code), "{code}");
+            }
+        }
+    }
+}
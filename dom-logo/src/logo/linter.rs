@@ -0,0 +1,444 @@
+// Warnings about code that parses but is likely wrong, found before it runs:
+// procedures never called, inputs never read, `:names` no procedure around
+// them has as an input, procedures that call themselves with no IF to stop
+// them, and REPEATs that never run.
+//
+// A procedure counts as called when a list or a word names it too, as it may
+// be run by RUN, APPLY or a template.
+//
+use super::parser::{same, Expression, Span};
+use super::value::Value;
+use super::Diagnostic;
+use std::collections::HashSet;
+
+/// A procedure definition, and what its body was seen doing
+struct Proc<'a> {
+    name: &'a str,
+    params: &'a [String],
+    span: &'a Span,
+    /// Lowercased inputs its body reads
+    read: HashSet<String>,
+    /// A call to itself outside of any IF, with the statement it is in
+    recursion: Option<&'a Span>,
+    /// An IF of its body throws, which is a way out of the recursion
+    escapes: bool,
+}
+
+/// Where in the program an expression is
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    /// The procedure whose body it is in, by index
+    proc: Option<usize>,
+    /// It is in an IF block
+    guarded: bool,
+    /// The statement it is part of
+    span: &'a Span,
+}
+
+struct Linter<'a> {
+    code: &'a str,
+    procs: Vec<Proc<'a>>,
+    /// Lowercased names of procedures called or named, and who calls them
+    calls: Vec<(String, Option<usize>)>,
+    /// `:names` read where no input has them, and the statement reading them
+    unbound: Vec<(&'a str, Option<usize>, &'a Span)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Lints a program's statements, as parsed from `code`
+pub fn lint(code: &str, exps: &[Expression]) -> Vec<Diagnostic> {
+    let top = Span {
+        line: 1,
+        column: 1,
+        start: 0,
+        end: code.len(),
+    };
+    let mut linter = Linter {
+        code,
+        procs: vec![],
+        calls: vec![],
+        unbound: vec![],
+        diagnostics: vec![],
+    };
+    linter.block(
+        exps,
+        Scope {
+            proc: None,
+            guarded: false,
+            span: &top,
+        },
+    );
+    linter.report();
+    linter.diagnostics
+}
+
+impl<'a> Linter<'a> {
+    fn block(&mut self, exps: &'a [Expression], scope: Scope<'a>) {
+        let mut scope = scope;
+        for exp in exps {
+            match exp {
+                Expression::Source(span) => scope.span = span,
+                exp => self.statement(exp, scope),
+            }
+        }
+    }
+
+    fn statement(&mut self, exp: &'a Expression, scope: Scope<'a>) {
+        match exp {
+            Expression::To(name, params, body) => {
+                self.procs.push(Proc {
+                    name,
+                    params,
+                    span: scope.span,
+                    read: HashSet::new(),
+                    recursion: None,
+                    escapes: false,
+                });
+                let proc = Some(self.procs.len() - 1);
+                self.block(
+                    body,
+                    Scope {
+                        proc,
+                        guarded: false,
+                        ..scope
+                    },
+                );
+            }
+            Expression::Repeat(count, body) => {
+                if let Expression::Number(n) = **count {
+                    if n <= 0.0 {
+                        self.diagnostics.push(Diagnostic {
+                            message: format!("REPEAT {n} never runs its block"),
+                            span: self.find(scope.span, &n.to_string()),
+                            related: None,
+                        });
+                    }
+                }
+                self.exp(count, scope);
+                self.block(body, scope);
+            }
+            Expression::If(condition, body) => {
+                self.exp(condition, scope);
+                self.block(
+                    body,
+                    Scope {
+                        guarded: true,
+                        ..scope
+                    },
+                );
+            }
+            Expression::Ask(ids, body) => {
+                for id in ids {
+                    self.exp(id, scope);
+                }
+                self.block(body, scope);
+            }
+            Expression::Catch(tag, body) => {
+                self.exp(tag, scope);
+                self.block(body, scope);
+            }
+            exp => self.exp(exp, scope),
+        }
+    }
+
+    fn exp(&mut self, exp: &'a Expression, scope: Scope<'a>) {
+        match exp {
+            Expression::Var(name) => match scope.proc {
+                Some(proc) if self.procs[proc].declares(name) => {
+                    self.procs[proc].read.insert(name.to_lowercase());
+                }
+                proc => self.unbound.push((name, proc, scope.span)),
+            },
+            Expression::Call(name, args) => {
+                if let Some(proc) = scope.proc {
+                    let own = &mut self.procs[proc];
+                    if same(own.name, name) && !scope.guarded {
+                        own.recursion.get_or_insert(scope.span);
+                    }
+                }
+                self.calls.push((name.to_lowercase(), scope.proc));
+                for arg in args {
+                    self.exp(arg, scope);
+                }
+            }
+            Expression::Throw(tag, value) => {
+                if let (Some(proc), true) = (scope.proc, scope.guarded) {
+                    self.procs[proc].escapes = true;
+                }
+                self.exp(tag, scope);
+                if let Some(value) = value {
+                    self.exp(value, scope);
+                }
+            }
+            Expression::Word(word) => self.word(word, scope),
+            Expression::List(items) => self.list(items, scope),
+            Expression::Tell(ids) => {
+                for id in ids {
+                    self.exp(id, scope);
+                }
+            }
+            Expression::Primitive(_, inputs) => {
+                for input in inputs {
+                    self.exp(input, scope);
+                }
+            }
+            Expression::Setpos(a, b)
+            | Expression::Apply(a, b)
+            | Expression::Foreach(a, b)
+            | Expression::Arc(a, b)
+            | Expression::Map(a, b)
+            | Expression::Filter(a, b)
+            | Expression::Reduce(a, b) => {
+                self.exp(a, scope);
+                self.exp(b, scope);
+            }
+            Expression::Setcolor(a, b, c)
+            | Expression::Condition(a, b, c)
+            | Expression::Math(a, b, c) => {
+                self.exp(a, scope);
+                self.exp(b, scope);
+                self.exp(c, scope);
+            }
+            Expression::Setpensize(x)
+            | Expression::Setturtle(x)
+            | Expression::Setspeed(x)
+            | Expression::Wait(x)
+            | Expression::Label(x)
+            | Expression::Print(x)
+            | Expression::Show(x)
+            | Expression::Type(x)
+            | Expression::Run(x)
+            | Expression::Forward(x)
+            | Expression::Back(x)
+            | Expression::Right(x)
+            | Expression::Left(x)
+            | Expression::Circle(x) => self.exp(x, scope),
+            Expression::To(..)
+            | Expression::Repeat(..)
+            | Expression::If(..)
+            | Expression::Ask(..)
+            | Expression::Catch(..) => self.statement(exp, scope),
+            _ => {}
+        }
+    }
+
+    /// Words of a list may be code run later, naming procedures and inputs
+    fn list(&mut self, items: &[Value], scope: Scope<'a>) {
+        for item in items {
+            match item {
+                Value::Word(word) => self.word(word, scope),
+                Value::List(items) => self.list(items, scope),
+                _ => {}
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str, scope: Scope<'a>) {
+        let word = word.trim_start_matches('"').to_lowercase();
+        if let Some(proc) = scope.proc {
+            if self.procs[proc].declares(&word) {
+                self.procs[proc].read.insert(word.clone());
+            }
+        }
+        self.calls.push((word, scope.proc));
+    }
+
+    fn report(&mut self) {
+        for (i, proc) in self.procs.iter().enumerate() {
+            let name = proc.name.to_lowercase();
+            if !self
+                .calls
+                .iter()
+                .any(|(called, caller)| *called == name && *caller != Some(i))
+            {
+                self.diagnostics.push(Diagnostic {
+                    message: format!("procedure `{}` is never called", proc.name),
+                    span: self.find(proc.span, proc.name),
+                    related: None,
+                });
+            }
+            for param in proc.params {
+                if !proc.read.contains(&param.to_lowercase()) {
+                    self.diagnostics.push(Diagnostic {
+                        message: format!("input `{param}` of `{}` is never used", proc.name),
+                        span: self.find(proc.span, param),
+                        related: None,
+                    });
+                }
+            }
+            if let (Some(span), false) = (proc.recursion, proc.escapes) {
+                self.diagnostics.push(Diagnostic {
+                    message: format!(
+                        "`{}` calls itself without an IF to stop it, so it never ends",
+                        proc.name
+                    ),
+                    span: self.find(span, proc.name),
+                    related: Some(self.find(proc.span, proc.name)),
+                });
+            }
+        }
+        for (name, proc, span) in &self.unbound {
+            let message = match proc {
+                Some(proc) => format!("`{name}` is not an input of `{}`", self.procs[*proc].name),
+                None => format!("`{name}` is used outside of any procedure with it as an input"),
+            };
+            // a procedure it may have been meant for
+            let related = self
+                .procs
+                .iter()
+                .find(|other| other.declares(name))
+                .map(|other| self.find(other.span, name));
+            self.diagnostics.push(Diagnostic {
+                message,
+                span: self.find(span, name),
+                related,
+            });
+        }
+        self.diagnostics
+            .sort_by_key(|diagnostic| diagnostic.span.start);
+    }
+
+    /// Where `text` is first written in `span` as a whole token, whatever its
+    /// case, or all of `span` if it isn't
+    fn find(&self, span: &Span, text: &str) -> Span {
+        let source = &self.code[span.start..span.end];
+        let word = |c: char| c.is_alphanumeric() || c == '_' || c == ':' || c == '.';
+        let found = source.char_indices().map(|(i, _)| i).find(|&i| {
+            matches!(source.get(i..i + text.len()), Some(candidate) if same(candidate, text)
+                && !source[..i].ends_with(word)
+                && !source[i + text.len()..].starts_with(word))
+        });
+        found.map_or_else(
+            || span.clone(),
+            |i| {
+                let before = &source[..i];
+                let (line, column) = match before.rfind('\n') {
+                    Some(newline) => (
+                        span.line + before.matches('\n').count(),
+                        before[newline + 1..].chars().count() + 1,
+                    ),
+                    None => (span.line, span.column + before.chars().count()),
+                };
+                Span {
+                    line,
+                    column,
+                    start: span.start + i,
+                    end: span.start + i + text.len(),
+                }
+            },
+        )
+    }
+}
+
+impl Proc<'_> {
+    fn declares(&self, name: &str) -> bool {
+        self.params.iter().any(|param| same(param, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logo::locale::ENGLISH;
+    use crate::logo::parser::{parse_with, Signatures};
+
+    fn warnings(code: &str) -> Vec<(String, String)> {
+        let (exps, _) = parse_with(code, &Signatures::new(), &ENGLISH).expect("code must parse");
+        lint(code, &exps)
+            .into_iter()
+            .map(|d| (d.message, code[d.span.start..d.span.end].to_string()))
+            .collect()
+    }
+
+    fn messages(code: &str) -> Vec<String> {
+        warnings(code)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect()
+    }
+
+    #[test]
+    fn procedures_never_called() {
+        assert_eq!(
+            warnings("to square\nfd 10\nend"),
+            [("procedure `square` is never called".into(), "square".into())]
+        );
+        assert!(messages("to square\nfd 10\nend\nSQUARE").is_empty());
+    }
+
+    #[test]
+    fn procedures_named_by_words_or_lists_count_as_called() {
+        assert!(messages("to sq\nfd 10\nend\nrun [sq]").is_empty());
+        assert!(messages("to sq :n\nfd :n\nend\napply \"sq [10]").is_empty());
+    }
+
+    #[test]
+    fn procedures_calling_only_themselves_are_not_called() {
+        let messages = messages("to f :n\nif :n > 0 [f :n - 1]\nend");
+        assert_eq!(messages, ["procedure `f` is never called"]);
+    }
+
+    #[test]
+    fn inputs_never_used() {
+        assert_eq!(
+            warnings("to sq :size :unused\nfd :size\nend\nsq 1 2"),
+            [(
+                "input `:unused` of `sq` is never used".into(),
+                ":unused".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn names_outside_of_procedures_that_have_them() {
+        let code = "to sq :size\nfd :size\nend\nsq 1\nfd :size";
+        let (exps, _) = parse_with(code, &Signatures::new(), &ENGLISH).unwrap();
+        let diagnostics = lint(code, &exps);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(
+            diagnostic.message,
+            "`:size` is used outside of any procedure with it as an input"
+        );
+        assert_eq!((diagnostic.span.line, diagnostic.span.column), (5, 4));
+        let related = diagnostic.related.as_ref().expect("sq has :size");
+        assert_eq!((related.line, related.column), (1, 7));
+        assert_eq!(
+            messages("to a :x\nfd :y\nend\na 1"),
+            [
+                "input `:x` of `a` is never used",
+                "`:y` is not an input of `a`"
+            ]
+        );
+    }
+
+    #[test]
+    fn recursion_without_an_if() {
+        let code = "to spin\nrt 1\nspin\nend\nspin";
+        assert_eq!(
+            warnings(code),
+            [(
+                "`spin` calls itself without an IF to stop it, so it never ends".into(),
+                "spin".into()
+            )]
+        );
+        assert!(messages("to spin :n\nif :n > 0 [spin :n - 1]\nend\nspin 3").is_empty());
+    }
+
+    #[test]
+    fn recursion_that_an_if_throws_out_of() {
+        let code = "to spin\nif repcount > 5 [throw \"done]\nspin\nend\ncatch \"done [spin]";
+        assert!(messages(code).is_empty());
+    }
+
+    #[test]
+    fn repeats_that_never_run() {
+        assert_eq!(
+            warnings("repeat 0 [fd 10]\nrepeat -2 [fd 10]\nrepeat 1 [fd 10]"),
+            [
+                ("REPEAT 0 never runs its block".into(), "0".into()),
+                ("REPEAT -2 never runs its block".into(), "-2".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,448 @@
+// Keyword tables and error messages for the languages Logo can be written in.
+//
+// A locale adds its own spellings of keywords on top of the English ones,
+// which work everywhere, as do emoji. Spellings are matched whatever their case, and emoji
+// with or without their variation selector.
+//
+use super::{Expected, Kind, LogoError};
+
+pub struct Locale {
+    pub name: &'static str,
+    /// Spellings of keywords, and the English keyword each stands for
+    keywords: &'static [(&'static str, &'static str)],
+    /// Error messages, when the locale has its own
+    messages: Option<fn(&LogoError) -> String>,
+}
+
+pub const ENGLISH: Locale = Locale {
+    name: "logo",
+    keywords: &[],
+    messages: None,
+};
+
+pub const EMOJI: Locale = Locale {
+    name: "emoji",
+    keywords: &[
+        ("⬆️", "forward"),
+        ("👆", "forward"),
+        ("⬇️", "back"),
+        ("👇", "back"),
+        ("➡️", "right"),
+        ("👉", "right"),
+        ("⬅️", "left"),
+        ("👈", "left"),
+        ("🔁", "repeat"),
+        ("🧹", "clear"),
+    ],
+    messages: None,
+};
+
+pub const HEBREW: Locale = Locale {
+    name: "hebrew",
+    keywords: &[
+        ("קד", "forward"),
+        ("קדימה", "forward"),
+        ("אח", "back"),
+        ("אחורה", "back"),
+        ("ימ", "right"),
+        ("ימינה", "right"),
+        ("שמ", "left"),
+        ("שמאלה", "left"),
+        ("חזור", "repeat"),
+        ("למד", "to"),
+        ("סוף", "end"),
+        ("אם", "if"),
+        ("נקה", "clear"),
+        ("הדפס", "print"),
+        ("הרם", "penup"),
+        ("הורד", "pendown"),
+        ("תפוס", "catch"),
+        ("זרוק", "throw"),
+    ],
+    messages: Some(hebrew),
+};
+
+pub const SPANISH: Locale = Locale {
+    name: "spanish",
+    keywords: &[
+        ("avanza", "forward"),
+        ("av", "forward"),
+        ("retrocede", "back"),
+        ("re", "back"),
+        ("giraderecha", "right"),
+        ("gd", "right"),
+        ("giraizquierda", "left"),
+        ("gi", "left"),
+        ("repite", "repeat"),
+        ("para", "to"),
+        ("fin", "end"),
+        ("si", "if"),
+        ("borrapantalla", "clear"),
+        ("bp", "clear"),
+        ("escribe", "print"),
+        ("subelapiz", "penup"),
+        ("sl", "penup"),
+        ("bajalapiz", "pendown"),
+        ("bl", "pendown"),
+        ("atrapa", "catch"),
+        ("lanza", "throw"),
+    ],
+    messages: Some(spanish),
+};
+
+pub const LOCALES: &[&Locale] = &[&ENGLISH, &EMOJI, &HEBREW, &SPANISH];
+
+impl Locale {
+    /// The locale named `name`, or English when there is none
+    pub fn find(name: &str) -> &'static Self {
+        LOCALES
+            .iter()
+            .find(|locale| locale.name.eq_ignore_ascii_case(name))
+            .copied()
+            .unwrap_or(&ENGLISH)
+    }
+
+    /// The English keyword `text` is a spelling of, if it is one. Emoji
+    /// spell keywords whatever the locale.
+    pub fn keyword(&self, text: &str) -> Option<&'static str> {
+        let text = text.replace('\u{fe0f}', "").to_lowercase();
+        self.keywords
+            .iter()
+            .chain(EMOJI.keywords)
+            .find(|(spelling, _)| spelling.trim_end_matches('\u{fe0f}') == text)
+            .map(|(_, keyword)| *keyword)
+    }
+
+    /// This locale's spellings of the English keyword `english`
+    pub fn spellings<'a>(&'a self, english: &'a str) -> impl Iterator<Item = &'static str> + 'a {
+        self.keywords
+            .iter()
+            .filter(move |(_, keyword)| *keyword == english)
+            .map(|(spelling, _)| *spelling)
+    }
+
+    /// How this locale writes the English keyword `english` in a message:
+    /// in capitals, and in English when it has no spelling of its own
+    fn spelled(&self, english: &str) -> String {
+        self.spellings(english)
+            .next()
+            .unwrap_or(english)
+            .to_uppercase()
+    }
+
+    /// Describes an error in this locale's language
+    pub fn message(&self, err: &LogoError) -> String {
+        self.messages
+            .map_or_else(|| err.to_string(), |message| message(err))
+    }
+}
+
+fn hebrew(err: &LogoError) -> String {
+    match err {
+        LogoError::UndefinedExpression(x) => format!("לא מכיר את `{x}`"),
+        LogoError::NoSuchOperator(x) => format!("אין פעולה כזו: `{x}`"),
+        LogoError::UndefinedParameter(x) => format!("אין פרמטר כזה: `{x}`"),
+        LogoError::SyntaxError {
+            expected, found, ..
+        } => format!(
+            "שגיאת תחביר: ציפיתי ל{}, {}",
+            hebrew_expected(*expected),
+            found.as_ref().map_or_else(
+                || "אבל הקוד נגמר".into(),
+                |found| format!("קיבלתי `{found}`")
+            )
+        ),
+        LogoError::UnexpectedInput {
+            found,
+            expected,
+            span,
+        } => format!(
+            "`{found}` לא צפוי בשורה {}, עמודה {}: ציפיתי ל{}",
+            span.line,
+            span.column,
+            hebrew_expected(*expected)
+        ),
+        LogoError::RuntimeBudgetExceeded(n) => format!("חריגה מתקציב הריצה: `{n}`"),
+        LogoError::RecursionBudgetExceeded(n) => format!("חריגה מתקציב הרקורסיה: `{n}`"),
+        LogoError::OutOfBounds => "הצב יצא מגבולות הציור".into(),
+        LogoError::InvalidTurtle(x) => format!("מספר צב לא תקין: `{x}`"),
+        LogoError::TypeError { expected, got } => {
+            format!(
+                "שגיאת טיפוס: ציפיתי ל{}, קיבלתי `{got}`",
+                hebrew_kind(*expected)
+            )
+        }
+        LogoError::BadInput(name, input) => format!("`{name}` לא מקבל את `{input}` כקלט"),
+        LogoError::Throw(tag, _) => {
+            format!("לא נמצא {} עבור `{tag}`", HEBREW.spelled("catch"))
+        }
+        LogoError::UserError(_) => err.to_string(),
+        LogoError::ArityMismatch {
+            name,
+            expected,
+            got,
+            span,
+        } => format!(
+            "`{name}` מצפה ל-{expected} קלטים, קיבל {got}{}",
+            span.as_ref().map_or_else(String::new, |span| format!(
+                ", בשורה {}, עמודה {}",
+                span.line, span.column
+            ))
+        ),
+    }
+}
+
+fn hebrew_expected(expected: Expected) -> &'static str {
+    match expected {
+        Expected::Token => "מילה, מספר, :שם, פרוצדורה, `[`, `]` או פעולה",
+        Expected::Number => "מספר",
+        Expected::Statement => "פקודה או פרוצדורה",
+        Expected::Input => "קלט: מספר, מילה, :שם, רשימה או פעולה שמחזירה ערך",
+        Expected::Nothing => "ביטוי יחיד",
+        Expected::Block => "`[` שפותח בלוק",
+        Expected::Close => "`]`",
+        Expected::End => "`סוף`",
+        Expected::Open => "`[` לפני `]`",
+        Expected::To => "`למד` לפני `סוף`",
+        Expected::Comparison => "`<`, `>`, `==` או `!=`",
+        Expected::Name => "שם של פרוצדורה",
+        Expected::Unreserved => "שם של פרוצדורה שאינו מילת בקרה כמו `אם`, `חזור` או `סוף`",
+        Expected::Shape => "triangle, turtle, arrow, circle או `[`",
+        Expected::Coordinate => "קואורדינטה של מצולע",
+        Expected::Points => "לפחות 3 נקודות, כזוגות של x y",
+        Expected::Spaces => "רווחים סביב `-`, כמו `10 - 5`",
+    }
+}
+
+fn hebrew_kind(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Number => "מספר",
+        Kind::Boolean => "אמת או שקר",
+        Kind::List => "רשימה",
+        Kind::Word => "מילה",
+    }
+}
+
+fn spanish(err: &LogoError) -> String {
+    match err {
+        LogoError::UndefinedExpression(x) => format!("no sé cómo hacer `{x}`"),
+        LogoError::NoSuchOperator(x) => format!("no existe el operador `{x}`"),
+        LogoError::UndefinedParameter(x) => format!("no existe el parámetro `{x}`"),
+        LogoError::SyntaxError {
+            expected, found, ..
+        } => format!(
+            "error de sintaxis: se esperaba {}, {}",
+            spanish_expected(*expected),
+            found.as_ref().map_or_else(
+                || "pero el código terminó".into(),
+                |found| format!("se encontró `{found}`")
+            )
+        ),
+        LogoError::UnexpectedInput {
+            found,
+            expected,
+            span,
+        } => format!(
+            "`{found}` inesperado en la línea {}, columna {}: se esperaba {}",
+            span.line,
+            span.column,
+            spanish_expected(*expected)
+        ),
+        LogoError::RuntimeBudgetExceeded(n) => {
+            format!("se agotó el presupuesto de ejecución: `{n}`")
+        }
+        LogoError::RecursionBudgetExceeded(n) => {
+            format!("se agotó el presupuesto de recursión: `{n}`")
+        }
+        LogoError::OutOfBounds => "la tortuga se salió del dibujo".into(),
+        LogoError::InvalidTurtle(x) => format!("número de tortuga no válido: `{x}`"),
+        LogoError::TypeError { expected, got } => {
+            format!(
+                "error de tipo: se esperaba {}, se recibió `{got}`",
+                spanish_kind(*expected)
+            )
+        }
+        LogoError::BadInput(name, input) => {
+            format!("a `{name}` no le gusta `{input}` como entrada")
+        }
+        LogoError::Throw(tag, _) => {
+            format!("no hay {} para `{tag}`", SPANISH.spelled("catch"))
+        }
+        LogoError::UserError(_) => err.to_string(),
+        LogoError::ArityMismatch {
+            name,
+            expected,
+            got,
+            span,
+        } => format!(
+            "`{name}` espera {expected} entradas, recibió {got}{}",
+            span.as_ref().map_or_else(String::new, |span| format!(
+                ", en la línea {}, columna {}",
+                span.line, span.column
+            ))
+        ),
+    }
+}
+
+fn spanish_expected(expected: Expected) -> &'static str {
+    match expected {
+        Expected::Token => {
+            "una palabra, un número, un :nombre, un procedimiento, `[`, `]` o un operador"
+        }
+        Expected::Number => "un número",
+        Expected::Statement => "una orden o un procedimiento",
+        Expected::Input => {
+            "una entrada: un número, una palabra, un :nombre, una lista o una operación"
+        }
+        Expected::Nothing => "una sola expresión",
+        Expected::Block => "`[` para empezar un bloque",
+        Expected::Close => "`]`",
+        Expected::End => "`FIN`",
+        Expected::Open => "`[` antes de `]`",
+        Expected::To => "`PARA` antes de `FIN`",
+        Expected::Comparison => "`<`, `>`, `==` o `!=`",
+        Expected::Name => "un nombre de procedimiento",
+        Expected::Unreserved => {
+            "un nombre de procedimiento que no sea de control, como `SI`, `REPITE` o `FIN`"
+        }
+        Expected::Shape => "triangle, turtle, arrow, circle o `[`",
+        Expected::Coordinate => "una coordenada del polígono",
+        Expected::Points => "al menos 3 puntos, como pares x y",
+        Expected::Spaces => "espacios alrededor de `-`, como en `10 - 5`",
+    }
+}
+
+fn spanish_kind(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Number => "un número",
+        Kind::Boolean => "verdadero o falso",
+        Kind::List => "una lista",
+        Kind::Word => "una palabra",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logo::parser::{parse_with, Signatures};
+    use crate::logo::value::Value;
+
+    fn error(code: &str, locale: &Locale) -> LogoError {
+        match parse_with(code, &Signatures::new(), locale) {
+            Err(err) => err,
+            Ok(_) => panic!("`{code}` must not parse"),
+        }
+    }
+
+    #[test]
+    fn emoji_are_keywords_in_every_locale() {
+        for locale in LOCALES {
+            assert_eq!(locale.keyword("⬆️"), Some("forward"), "{}", locale.name);
+            assert_eq!(locale.keyword("⬆"), Some("forward"), "{}", locale.name);
+            assert_eq!(locale.keyword("🔁"), Some("repeat"), "{}", locale.name);
+        }
+    }
+
+    #[test]
+    fn keywords_are_matched_whatever_their_case() {
+        assert_eq!(SPANISH.keyword("AVANZA"), Some("forward"));
+        assert_eq!(HEBREW.keyword("קד"), Some("forward"));
+        assert_eq!(ENGLISH.keyword("קד"), None);
+        assert_eq!(ENGLISH.keyword("forward"), None);
+    }
+
+    #[test]
+    fn unknown_locales_are_english() {
+        assert_eq!(Locale::find("klingon").name, "logo");
+        assert_eq!(Locale::find("Hebrew").name, "hebrew");
+    }
+
+    #[test]
+    fn syntax_errors_say_what_was_expected() {
+        let err = error("repeat 4 fd 10", &ENGLISH);
+        assert!(matches!(
+            &err,
+            LogoError::SyntaxError { expected: Expected::Block, found: Some(found), .. }
+                if found == "fd"
+        ));
+        assert!(ENGLISH
+            .message(&err)
+            .starts_with("syntax error: expected `[` to start a block, got `fd`"));
+        assert_eq!(
+            HEBREW.message(&err),
+            "שגיאת תחביר: ציפיתי ל`[` שפותח בלוק, קיבלתי `fd`"
+        );
+        assert_eq!(
+            SPANISH.message(&err),
+            "error de sintaxis: se esperaba `[` para empezar un bloque, se encontró `fd`"
+        );
+    }
+
+    #[test]
+    fn syntax_errors_at_the_end_of_the_code() {
+        let err = error("repeat 4 [fd 10", &HEBREW);
+        assert!(matches!(
+            err,
+            LogoError::SyntaxError {
+                expected: Expected::Close,
+                found: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            HEBREW.message(&err),
+            "שגיאת תחביר: ציפיתי ל`]`, אבל הקוד נגמר"
+        );
+    }
+
+    #[test]
+    fn unexpected_input_is_translated() {
+        let err = error("fd 5O", &SPANISH);
+        assert!(matches!(
+            err,
+            LogoError::UnexpectedInput {
+                expected: Expected::Number,
+                ..
+            }
+        ));
+        assert_eq!(
+            SPANISH.message(&err),
+            "`5O` inesperado en la línea 1, columna 4: se esperaba un número"
+        );
+        assert_eq!(
+            ENGLISH.message(&err),
+            "unexpected `5O` on line 1, column 4: expected a number"
+        );
+    }
+
+    #[test]
+    fn run_time_errors_are_translated_whole() {
+        let err = Value::List(vec![]).type_error(Kind::Number);
+        assert_eq!(
+            ENGLISH.message(&err),
+            "type error: expected number, got `[]`"
+        );
+        assert_eq!(
+            HEBREW.message(&err),
+            "שגיאת טיפוס: ציפיתי למספר, קיבלתי `[]`"
+        );
+        assert_eq!(
+            SPANISH.message(&err),
+            "error de tipo: se esperaba un número, se recibió `[]`"
+        );
+        let err = LogoError::Throw("oops".into(), None);
+        assert_eq!(HEBREW.message(&err), "לא נמצא תפוס עבור `oops`");
+        assert_eq!(SPANISH.message(&err), "no hay ATRAPA para `oops`");
+    }
+
+    #[test]
+    fn subtracting_without_spaces_is_explained() {
+        let err = error("fd 10-5", &HEBREW);
+        assert_eq!(
+            ENGLISH.message(&err),
+            "unexpected `10-5` on line 1, column 4: expected spaces around `-`, as in `10 - 5`"
+        );
+        assert!(HEBREW.message(&err).contains("`10 - 5`"));
+        assert!(SPANISH.message(&err).contains("`10 - 5`"));
+    }
+}
@@ -1,12 +1,24 @@
 use std::collections::VecDeque;
+use std::fmt;
 
+use serde::Serialize;
 use thiserror::Error;
 
-use self::parser::Token;
+use self::parser::{Span, Token};
+use self::value::Value;
 
+pub mod compiler;
+pub mod debugger;
 pub mod executor;
+pub mod formatter;
+pub mod linter;
+pub mod locale;
 pub mod parser;
+pub mod primitives;
+pub mod profiler;
+pub mod service;
 pub mod turtle;
+pub mod value;
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Error, Debug)]
@@ -20,14 +32,166 @@ pub enum LogoError {
     #[error("no such parameter: `{0}`")]
     UndefinedParameter(String),
 
-    #[error("syntax error: `{err}`\ntokens:\n{tokens:?}")]
+    #[error(
+        "syntax error: expected {expected}, got {}\ntokens:\n{tokens:?}",
+        got(found)
+    )]
     SyntaxError {
-        err: String,
+        expected: Expected,
+        /// What was there instead, as written, or nothing at the end of
+        /// the code
+        found: Option<String>,
         tokens: VecDeque<Token>,
     },
+    #[error(
+        "unexpected `{found}` on line {}, column {}: expected {expected}",
+        span.line,
+        span.column
+    )]
+    UnexpectedInput {
+        found: String,
+        expected: Expected,
+        span: Span,
+    },
+
     #[error("runtime budget exceeded: `{0}`")]
     RuntimeBudgetExceeded(usize),
 
     #[error("recursion budget exceeded: `{0}`")]
     RecursionBudgetExceeded(usize),
+
+    #[error("turtle out of bounds")]
+    OutOfBounds,
+
+    #[error("invalid turtle id: `{0}`")]
+    InvalidTurtle(String),
+
+    #[error("type error: expected {expected}, got `{got}`")]
+    TypeError { expected: Kind, got: String },
+
+    #[error("`{0}` doesn't like `{1}` as input")]
+    BadInput(String, String),
+
+    #[error("can't find a CATCH for `{0}`")]
+    Throw(String, Option<Value>),
+
+    #[error("{}", .0.printed())]
+    UserError(Value),
+
+    #[error("`{name}` expects {expected} inputs, got {got}{}", at(span))]
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        got: usize,
+        /// Where the call is, when it is known before running
+        span: Option<Span>,
+    },
+}
+
+/// What the parser looked for where the code went wrong. Locales describe
+/// each in their own words; `Display` is the English description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// Anything that can be a token
+    Token,
+    Number,
+    /// A command or a procedure to start a statement with
+    Statement,
+    /// A value, a `:name` or a reporter
+    Input,
+    /// Nothing more after a single expression
+    Nothing,
+    /// `[` to start a block with
+    Block,
+    /// `]` to close a list or a block
+    Close,
+    /// `END` to close a procedure
+    End,
+    /// `[` for a `]` to close
+    Open,
+    /// `TO` for an `END` to close
+    To,
+    Comparison,
+    /// A name for a procedure
+    Name,
+    /// A name for a procedure that isn't a control keyword
+    Unreserved,
+    Shape,
+    /// A number of a polygon shape
+    Coordinate,
+    /// At least 3 points, as x y pairs, for a polygon shape
+    Points,
+    /// Spaces around a `-` that subtracts, rather than a negative number
+    Spaces,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Token => "a word, a number, a :name, a procedure, `[`, `]` or an operator",
+            Self::Number => "a number",
+            Self::Statement => "a command or a procedure",
+            Self::Input => "an input: a number, a word, a :name, a list or a reporter",
+            Self::Nothing => "a single expression",
+            Self::Block => "`[` to start a block",
+            Self::Close => "`]`",
+            Self::End => "`END`",
+            Self::Open => "`[` before `]`",
+            Self::To => "`TO` before `END`",
+            Self::Comparison => "`<`, `>`, `==` or `!=`",
+            Self::Name => "a procedure name",
+            Self::Unreserved => {
+                "a procedure name other than `TO`, `END`, `IF`, `REPEAT`, `CATCH`, `THROW` or `ASK`"
+            }
+            Self::Shape => "triangle, turtle, arrow, circle or `[`",
+            Self::Coordinate => "a polygon coordinate",
+            Self::Points => "at least 3 points given as x y pairs",
+            Self::Spaces => "spaces around `-`, as in `10 - 5`",
+        })
+    }
+}
+
+/// What kind of value an input had to be. Locales describe each in their
+/// own words; `Display` is the English description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Number,
+    /// `true` or `false`
+    Boolean,
+    List,
+    Word,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Number => "number",
+            Self::Boolean => "true or false",
+            Self::List => "list",
+            Self::Word => "word",
+        })
+    }
+}
+
+/// What was found where something else was expected
+fn got(found: &Option<String>) -> String {
+    found
+        .as_ref()
+        .map_or_else(|| "nothing".into(), |found| format!("`{found}`"))
+}
+
+/// Where in the code an error is, if it is known
+fn at(span: &Option<Span>) -> String {
+    span.as_ref().map_or_else(String::new, |span| {
+        format!(", on line {}, column {}", span.line, span.column)
+    })
+}
+
+/// A warning about the code, pointing at where it applies and, when there is
+/// one, at a related place such as an earlier definition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub related: Option<Span>,
 }
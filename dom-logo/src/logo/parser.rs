@@ -5,9 +5,15 @@
 //
 // Meanwhile kicking off with adapting parser code from ptrkalm/logo_interpreter
 //
-use crate::logo::LogoError;
+use crate::logo::locale::Locale;
+use crate::logo::turtle::Shape;
+use crate::logo::value::Value;
+use crate::logo::{Diagnostic, Expected, LogoError};
 use regex::Regex;
-use std::collections::VecDeque;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::sync::LazyLock;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
@@ -17,6 +23,33 @@ pub enum Token {
     Pendown,
     Setcolor,
     Setpensize,
+    Showturtle,
+    Hideturtle,
+    Setshape,
+    Stamp,
+    Fence,
+    Window,
+    Tell,
+    Ask,
+    Who,
+    Repcount,
+    Setturtle,
+    Setspeed,
+    Wait,
+    Label,
+    Print,
+    Show,
+    Type,
+    Run,
+    Apply,
+    Map,
+    Filter,
+    Reduce,
+    Foreach,
+    Catch,
+    Throw,
+    Error,
+    Primitive(Primitive),
     Forward,
     Back,
     Right,
@@ -31,6 +64,7 @@ pub enum Token {
     Number(f32),
     Ident(String),
     Var(String),
+    Word(String),
     If,
     Gtr,
     Less,
@@ -50,6 +84,26 @@ pub enum Expression {
     Pendown,
     Setcolor(Box<Expression>, Box<Expression>, Box<Expression>),
     Setpensize(Box<Expression>),
+    Showturtle,
+    Hideturtle,
+    Setshape(Shape),
+    Stamp,
+    Fence,
+    Window,
+    Tell(Vec<Expression>),
+    Ask(Vec<Expression>, Vec<Expression>),
+    Setturtle(Box<Expression>),
+    Setspeed(Box<Expression>),
+    Wait(Box<Expression>),
+    Label(Box<Expression>),
+    Print(Box<Expression>),
+    Show(Box<Expression>),
+    Type(Box<Expression>),
+    Run(Box<Expression>),
+    Apply(Box<Expression>, Box<Expression>),
+    Foreach(Box<Expression>, Box<Expression>),
+    Catch(Box<Expression>, Vec<Expression>),
+    Throw(Box<Expression>, Option<Box<Expression>>),
 
     Forward(Box<Expression>),
     Back(Box<Expression>),
@@ -58,12 +112,24 @@ pub enum Expression {
     Arc(Box<Expression>, Box<Expression>),
     Circle(Box<Expression>),
 
+    /// Where the statement that follows it is in the source
+    Source(Span),
+
     Repeat(Box<Expression>, Vec<Expression>),
     To(String, Vec<String>, Vec<Expression>),
     Call(String, Vec<Expression>),
 
     Number(f32),
     Var(String),
+    Word(String),
+    List(Vec<Value>),
+    Who,
+    Repcount,
+    Error,
+    Primitive(Primitive, Vec<Expression>),
+    Map(Box<Expression>, Box<Expression>),
+    Filter(Box<Expression>, Box<Expression>),
+    Reduce(Box<Expression>, Box<Expression>),
 
     If(Box<Expression>, Vec<Expression>),
     Condition(Box<Expression>, Box<Expression>, Box<Expression>),
@@ -79,81 +145,984 @@ pub enum Expression {
     Div,
 }
 
-pub fn parse(code: &str) -> Result<Vec<Expression>, LogoError> {
-    let mut tokens = tokenize(code.to_lowercase().as_str());
-    to_ast(&mut tokens)
-}
-
-fn tokenize(code: &str) -> VecDeque<Token> {
-    let mut tokens: VecDeque<Token> = VecDeque::new();
-    let regex = Regex::new(r":*[a-zA-Z]+[0-9]?+|-?\d+(\.\d+)?|(\[|\]|!=|==|<|>|\+|-|\*|/)")
-        .expect("regex must compile");
-    for token in regex.find_iter(code).map(|x| x.as_str()) {
-        tokens.push_back(match token {
-            "clear" | "cs" => Token::Clear,
-            "setpos" => Token::Setpos,
-            "penup" | "pu" => Token::Penup,
-            "pendown" | "pd" => Token::Pendown,
-            "setcolor" | "sc" => Token::Setcolor,
-            "setpensize" | "ps" => Token::Setpensize,
-            "forward" | "fd" => Token::Forward,
-            "circle" | "ci" => Token::Circle,
-            "arc" => Token::Arc,
-            "back" | "bk" => Token::Back,
-            "right" | "rt" => Token::Right,
-            "left" | "lt" => Token::Left,
-            "repeat" | "rp" => Token::Repeat,
-            "[" => Token::LBracket,
-            "]" => Token::RBracket,
-            "to" => Token::To,
-            "end" => Token::End,
-            "if" => Token::If,
-            ">" => Token::Gtr,
-            "<" => Token::Less,
-            "==" => Token::Eq,
-            "!=" => Token::Neq,
-            "+" => Token::Add,
-            "-" => Token::Sub,
-            "*" => Token::Mul,
-            "/" => Token::Div,
-            _ => token.parse::<f32>().map_or_else(
-                |_| {
-                    let string = String::from(token);
-                    match string.chars().next().expect("string cannot be empty") {
-                        ':' => Token::Var(string),
-                        _ => Token::Ident(string),
+/// Primitives that report a value computed from their inputs alone
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Primitive {
+    First,
+    Butfirst,
+    Last,
+    Item,
+    Fput,
+    Lput,
+    Count,
+    Word,
+    Sentence,
+    Emptyp,
+    Memberp,
+    List,
+}
+
+impl Primitive {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::First => "first",
+            Self::Butfirst => "butfirst",
+            Self::Last => "last",
+            Self::Item => "item",
+            Self::Fput => "fput",
+            Self::Lput => "lput",
+            Self::Count => "count",
+            Self::Word => "word",
+            Self::Sentence => "sentence",
+            Self::Emptyp => "emptyp",
+            Self::Memberp => "memberp",
+            Self::List => "list",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Self::First | Self::Butfirst | Self::Last | Self::Count | Self::Emptyp => 1,
+            Self::Item
+            | Self::Fput
+            | Self::Lput
+            | Self::Word
+            | Self::Sentence
+            | Self::Memberp
+            | Self::List => 2,
+        }
+    }
+}
+
+/// Where a token sits in the source: 1-based line and column, and the byte
+/// range it spans
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Tokens still to parse, along with how and where each token of the code
+/// was written
+#[derive(Debug, Default)]
+pub struct Tokens {
+    queue: VecDeque<Token>,
+    texts: Vec<String>,
+    spans: Vec<Span>,
+}
+
+impl Tokens {
+    fn push(&mut self, token: Token, text: &str, span: &Span) {
+        self.queue.push_back(token);
+        self.texts.push(text.to_string());
+        self.spans.push(span.clone());
+    }
+
+    /// How the token last taken off the front was written
+    fn taken(&self) -> &str {
+        &self.texts[self.texts.len() - self.queue.len() - 1]
+    }
+
+    /// Where the token last taken off the front was written
+    fn taken_span(&self) -> &Span {
+        &self.spans[self.spans.len() - self.queue.len() - 1]
+    }
+}
+
+impl Deref for Tokens {
+    type Target = VecDeque<Token>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.queue
+    }
+}
+
+impl DerefMut for Tokens {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.queue
+    }
+}
+
+impl IntoIterator for Tokens {
+    type Item = Token;
+    type IntoIter = std::collections::vec_deque::IntoIter<Token>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.queue.into_iter()
+    }
+}
+
+/// Number of inputs of each procedure, by lowercased name
+pub type Signatures = HashMap<String, usize>;
+
+/// Parses code that may call procedures defined elsewhere, as with `RUN`,
+/// along with warnings about its procedure definitions
+pub fn parse_with(
+    code: &str,
+    known: &Signatures,
+    locale: &Locale,
+) -> Result<(Vec<Expression>, Vec<Diagnostic>), LogoError> {
+    let found = lex_code(code)?;
+    // definitions outside of brackets are certain. Those within blocks are
+    // only told from lists by parsing, so the code is parsed again once they
+    // are found, for them to shadow primitives too. If it doesn't parse at
+    // first, it may be for a call to such a procedure, so every `TO` is tried.
+    let mut defining = outside_brackets(&found, locale);
+    let (mut failed, mut refined) = (None, false);
+    loop {
+        let (mut tokens, spans) = shadow(&found, &defining, locale);
+        let (declared, warnings) = declarations(&tokens, &spans, &defining, locale);
+        let mut procs = known.clone();
+        procs.extend(declared);
+        match to_ast(&mut tokens, &spans, &procs) {
+            Ok(exps) => {
+                let mut parsed = BTreeSet::new();
+                definitions(&exps, &spans, &mut parsed);
+                if parsed == defining || refined {
+                    return Ok((exps, warnings));
+                }
+                defining = parsed;
+                refined = true;
+            }
+            Err(err) if failed.is_none() => {
+                failed = Some(err);
+                defining = every_to(&found, locale);
+            }
+            Err(err) => return Err(failed.unwrap_or(err)),
+        }
+    }
+}
+
+/// Lowercased names of the procedures code calls without defining them,
+/// whose definitions elsewhere decide how it parses
+pub fn called(code: &str, locale: &Locale) -> Result<HashSet<String>, LogoError> {
+    let (tokens, _) = tokenize(code, locale)?;
+    let mut names = HashSet::new();
+    let mut defined = HashSet::new();
+    let mut previous = None;
+    for token in tokens.iter() {
+        if let Token::Ident(name) = token {
+            if previous == Some(&Token::To) {
+                defined.insert(name.to_lowercase());
+            } else {
+                names.insert(name.to_lowercase());
+            }
+        }
+        previous = Some(token);
+    }
+    Ok(&names - &defined)
+}
+
+/// Parses a single expression, such as a `MAP` template
+pub fn parse_expression(code: &str, locale: &Locale) -> Result<Expression, LogoError> {
+    let (mut tokens, _) = tokenize(code, locale)?;
+    let exp = build_condition(&mut tokens)?;
+    match tokens.pop_front() {
+        None => Ok(exp),
+        Some(_) => Err(syntax_error(
+            Expected::Nothing,
+            Some(tokens.taken()),
+            &tokens,
+        )),
+    }
+}
+
+/// Splits code into tokens, keeping their case. Keywords are matched
+/// whatever their case, and so are names, once they are interned.
+/// Comments run from `;` to the end of the line, and a `~` ending a line
+/// joins it with the next, comments included. Anything else that isn't
+/// whitespace is an error. Names may be in any script, and keywords in
+/// `locale`'s language as well as in English.
+pub fn tokenize(code: &str, locale: &Locale) -> Result<(Tokens, Vec<Span>), LogoError> {
+    let found = lex_code(code)?;
+    Ok(shadow(&found, &outside_brackets(&found, locale), locale))
+}
+
+/// The text of the tokens of code, leaving out comments and line
+/// continuations
+fn lex_code(code: &str) -> Result<Vec<(Span, &str)>, LogoError> {
+    Ok(lex(code)?
+        .into_iter()
+        .filter(|(_, text)| !text.starts_with([';', '~']))
+        .collect())
+}
+
+/// Where every `TO` is, lists included
+fn every_to(found: &[(Span, &str)], locale: &Locale) -> BTreeSet<usize> {
+    (0..found.len())
+        .filter(|&i| keyword(locale.keyword(found[i].1).unwrap_or(found[i].1)) == Token::To)
+        .collect()
+}
+
+/// Where the `TO`s outside of any brackets are, which can only start a
+/// definition
+fn outside_brackets(found: &[(Span, &str)], locale: &Locale) -> BTreeSet<usize> {
+    let mut depth = 0_usize;
+    let mut defining = BTreeSet::new();
+    for (i, (_, text)) in found.iter().enumerate() {
+        match *text {
+            "[" => depth += 1,
+            "]" => depth = depth.saturating_sub(1),
+            text if depth == 0 && keyword(locale.keyword(text).unwrap_or(text)) == Token::To => {
+                defining.insert(i);
+            }
+            _ => {}
+        }
+    }
+    defining
+}
+
+/// Reads the text of each token, where a procedure defined by a `TO` in
+/// `defining` shadows the primitive it is named like, everywhere in the code
+fn shadow(
+    found: &[(Span, &str)],
+    defining: &BTreeSet<usize>,
+    locale: &Locale,
+) -> (Tokens, Vec<Span>) {
+    let procs: HashSet<String> = defining
+        .iter()
+        .filter_map(|i| found.get(i + 1))
+        .map(|(_, text)| text.to_lowercase())
+        .collect();
+    let mut tokens = Tokens::default();
+    let mut spans = vec![];
+    for (span, text) in found {
+        spans.push(span.clone());
+        let token = keyword(locale.keyword(text).unwrap_or(text));
+        tokens.push(
+            if procs.contains(&text.to_lowercase()) && is_shadowable(&token) {
+                Token::Ident((*text).to_string())
+            } else {
+                token
+            },
+            text,
+            span,
+        );
+    }
+    (tokens, spans)
+}
+
+/// Adds where each `TO` parsed in `exps` is to `defining`, blocks included
+fn definitions(exps: &[Expression], spans: &[Span], defining: &mut BTreeSet<usize>) {
+    let mut source = None;
+    for e in exps {
+        match e {
+            Expression::Source(span) => source = Some(span.start),
+            Expression::To(_, _, body) => {
+                if let Some(start) = source {
+                    if let Ok(i) = spans.binary_search_by_key(&start, |span| span.start) {
+                        defining.insert(i);
                     }
-                },
-                Token::Number,
-            ),
-        });
+                }
+                definitions(body, spans, defining);
+            }
+            Expression::Repeat(_, body)
+            | Expression::If(_, body)
+            | Expression::Ask(_, body)
+            | Expression::Catch(_, body) => definitions(body, spans, defining),
+            _ => {}
+        }
     }
-    tokens
 }
 
-fn to_ast(tokens: &mut VecDeque<Token>) -> Result<Vec<Expression>, LogoError> {
+/// Where the comments are in code, in order
+pub fn comments(code: &str) -> Result<Vec<Span>, LogoError> {
+    Ok(lex(code)?
+        .into_iter()
+        .filter(|(_, text)| text.starts_with(';'))
+        .map(|(span, _)| span)
+        .collect())
+}
+
+/// The text of a token, a comment or a line continuation
+static TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#";(?:[^\n]*~\r?\n)*[^\n]*|~[ \t]*(?:\r?\n|$)|"[^\s\[\];]*|\?\d?|:*[\p{L}_][\p{L}\p{M}\p{N}_]*|\p{Extended_Pictographic}[\x{FE0F}\x{200D}\p{Extended_Pictographic}\p{Emoji_Modifier}]*|-?\d+(\.\d+)?|(\[|\]|!=|==|<|>|\+|-|\*|/|#)"#,
+    )
+    .expect("regex must compile")
+});
+
+/// Splits code into the text of its tokens, comments and line continuations.
+/// Between brackets, a run of anything but spaces, brackets and comments
+/// that doesn't split into tokens is a single word, as `hello,` in
+/// `[hello, world]`.
+fn lex(code: &str) -> Result<Vec<(Span, &str)>, LogoError> {
+    let mut found: Vec<(Span, &str)> = vec![];
+    let (mut line, mut line_start, mut scanned) = (1, 0, 0);
+    let mut span = |start: usize, end: usize| {
+        for (i, c) in code[scanned..start].char_indices() {
+            if c == '\n' {
+                line += 1;
+                line_start = scanned + i + 1;
+            }
+        }
+        scanned = start;
+        Span {
+            line,
+            column: code[line_start..start].chars().count() + 1,
+            start,
+            end,
+        }
+    };
+    let (mut end, mut depth) = (0, 0_usize);
+    while let Some(i) = code[end..].find(|c: char| !c.is_whitespace()) {
+        let start = end + i;
+        if depth > 0 {
+            let run = code[start..]
+                .find(|c: char| c.is_whitespace() || matches!(c, '[' | ']' | ';'))
+                .map_or(code.len(), |i| start + i);
+            if run > start && !splits(&code[start..run]) {
+                found.push((span(start, run), &code[start..run]));
+                end = run;
+                continue;
+            }
+        }
+        let m = match TOKEN.find_at(code, start) {
+            Some(m) if m.start() == start => m,
+            _ => {
+                let c = code[start..].chars().next().expect("code is left");
+                return Err(unexpected(
+                    c.to_string(),
+                    span(start, start + c.len_utf8()),
+                    Expected::Token,
+                ));
+            }
+        };
+        end = m.end();
+        let text = m.as_str();
+        let rest = runs_into(code, &m);
+        if rest > end {
+            return Err(unexpected(
+                code[start..rest].to_string(),
+                span(start, rest),
+                Expected::Number,
+            ));
+        }
+        if let Some((previous, _)) = found.last() {
+            if glued(&code[previous.start..previous.end], previous.end, &m) {
+                let from = previous.start;
+                return Err(unexpected(
+                    code[from..end].to_string(),
+                    span(from, end),
+                    Expected::Spaces,
+                ));
+            }
+        }
+        match text {
+            "[" => depth += 1,
+            "]" => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        found.push((span(start, end), text));
+    }
+    Ok(found)
+}
+
+/// Where a number runs into letters up to, as in `5O`, or the end of the
+/// token `m` when it doesn't
+fn runs_into(code: &str, m: &regex::Match) -> usize {
+    if m.as_str().parse::<f32>().is_err() {
+        return m.end();
+    }
+    code[m.end()..]
+        .find(|c: char| !c.is_alphanumeric() && c != '_' && c != '.')
+        .map_or(code.len(), |i| m.end() + i)
+}
+
+/// Whether `m` is a negative number right after a name or a number ending at
+/// `end`, as in `10-5`, rather than a `-` subtracting
+fn glued(previous: &str, end: usize, m: &regex::Match) -> bool {
+    m.start() == end
+        && m.as_str().len() > 1
+        && m.as_str().starts_with('-')
+        && previous.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether a run of code with no spaces, brackets or comments in it splits
+/// into tokens
+fn splits(run: &str) -> bool {
+    let mut end = 0;
+    let mut previous = "";
+    for m in TOKEN.find_iter(run) {
+        if m.start() != end
+            || m.as_str().starts_with(['~', ';'])
+            || runs_into(run, &m) > m.end()
+            || glued(previous, end, &m)
+        {
+            return false;
+        }
+        end = m.end();
+        previous = m.as_str();
+    }
+    end == run.len()
+}
+
+fn unexpected(found: String, span: Span, expected: Expected) -> LogoError {
+    LogoError::UnexpectedInput {
+        found,
+        expected,
+        span,
+    }
+}
+
+/// What the parser expected, and the token it found instead if there is one
+fn syntax_error(expected: Expected, found: Option<&str>, tokens: &VecDeque<Token>) -> LogoError {
+    LogoError::SyntaxError {
+        expected,
+        found: found.map(String::from),
+        tokens: tokens.clone(),
+    }
+}
+
+/// A keyword: its full name and short spellings, the inputs and description
+/// the editor shows for it, and the token it reads as
+pub struct Keyword {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// Its inputs as `:name`s, `[block]` for a block
+    pub inputs: &'static str,
+    pub description: &'static str,
+    pub token: Token,
+}
+
+/// Every keyword, in the order the editor offers them
+pub const KEYWORDS: &[Keyword] = &[
+    Keyword {
+        name: "forward",
+        aliases: &["fd"],
+        inputs: ":distance",
+        description: "moves the turtle forward, drawing a line when the pen is down",
+        token: Token::Forward,
+    },
+    Keyword {
+        name: "back",
+        aliases: &["bk"],
+        inputs: ":distance",
+        description: "moves the turtle back, drawing a line when the pen is down",
+        token: Token::Back,
+    },
+    Keyword {
+        name: "right",
+        aliases: &["rt"],
+        inputs: ":degrees",
+        description: "turns the turtle right, clockwise",
+        token: Token::Right,
+    },
+    Keyword {
+        name: "left",
+        aliases: &["lt"],
+        inputs: ":degrees",
+        description: "turns the turtle left, counterclockwise",
+        token: Token::Left,
+    },
+    Keyword {
+        name: "arc",
+        aliases: &[],
+        inputs: ":angle :radius",
+        description: "draws an arc of `angle` degrees around the turtle",
+        token: Token::Arc,
+    },
+    Keyword {
+        name: "circle",
+        aliases: &["ci"],
+        inputs: ":radius",
+        description: "draws a circle around the turtle",
+        token: Token::Circle,
+    },
+    Keyword {
+        name: "clear",
+        aliases: &["cs"],
+        inputs: "",
+        description: "clears the drawing",
+        token: Token::Clear,
+    },
+    Keyword {
+        name: "setpos",
+        aliases: &[],
+        inputs: ":x :y",
+        description: "moves the turtle to `x`, `y`",
+        token: Token::Setpos,
+    },
+    Keyword {
+        name: "penup",
+        aliases: &["pu"],
+        inputs: "",
+        description: "lifts the pen, so the turtle moves without drawing",
+        token: Token::Penup,
+    },
+    Keyword {
+        name: "pendown",
+        aliases: &["pd"],
+        inputs: "",
+        description: "puts the pen down, so the turtle draws as it moves",
+        token: Token::Pendown,
+    },
+    Keyword {
+        name: "setcolor",
+        aliases: &["sc"],
+        inputs: ":red :green :blue",
+        description: "sets the pen color, each part from 0 to 255",
+        token: Token::Setcolor,
+    },
+    Keyword {
+        name: "setpensize",
+        aliases: &["ps"],
+        inputs: ":size",
+        description: "sets how thick the pen draws",
+        token: Token::Setpensize,
+    },
+    Keyword {
+        name: "showturtle",
+        aliases: &["st"],
+        inputs: "",
+        description: "shows the turtle",
+        token: Token::Showturtle,
+    },
+    Keyword {
+        name: "hideturtle",
+        aliases: &["ht"],
+        inputs: "",
+        description: "hides the turtle",
+        token: Token::Hideturtle,
+    },
+    Keyword {
+        name: "setshape",
+        aliases: &[],
+        inputs: ":shape",
+        description:
+            "sets the turtle's shape: triangle, turtle, arrow, circle or a list of x y points",
+        token: Token::Setshape,
+    },
+    Keyword {
+        name: "stamp",
+        aliases: &[],
+        inputs: "",
+        description: "prints the turtle's shape onto the drawing",
+        token: Token::Stamp,
+    },
+    Keyword {
+        name: "fence",
+        aliases: &[],
+        inputs: "",
+        description: "keeps turtles on the canvas: a move off it fails with an error",
+        token: Token::Fence,
+    },
+    Keyword {
+        name: "window",
+        aliases: &[],
+        inputs: "",
+        description: "lets turtles move off the canvas again",
+        token: Token::Window,
+    },
+    Keyword {
+        name: "tell",
+        aliases: &[],
+        inputs: ":turtles",
+        description: "sends the commands that follow to a turtle, or a list of them",
+        token: Token::Tell,
+    },
+    Keyword {
+        name: "ask",
+        aliases: &[],
+        inputs: ":turtles [block]",
+        description: "runs a block with other turtles, then goes back to these",
+        token: Token::Ask,
+    },
+    Keyword {
+        name: "who",
+        aliases: &[],
+        inputs: "",
+        description: "reports the turtle, or the list of turtles, commands go to",
+        token: Token::Who,
+    },
+    Keyword {
+        name: "setturtle",
+        aliases: &[],
+        inputs: ":id",
+        description: "sends the commands that follow to one turtle",
+        token: Token::Setturtle,
+    },
+    Keyword {
+        name: "setspeed",
+        aliases: &[],
+        inputs: ":speed",
+        description: "sets how far the turtle moves in a frame when played",
+        token: Token::Setspeed,
+    },
+    Keyword {
+        name: "wait",
+        aliases: &[],
+        inputs: ":ticks",
+        description: "pauses the turtle for a number of ticks when played",
+        token: Token::Wait,
+    },
+    Keyword {
+        name: "label",
+        aliases: &[],
+        inputs: ":thing",
+        description: "writes a value on the drawing, where the turtle is",
+        token: Token::Label,
+    },
+    Keyword {
+        name: "print",
+        aliases: &["pr"],
+        inputs: ":thing",
+        description: "writes a value on a line of output, a list without brackets",
+        token: Token::Print,
+    },
+    Keyword {
+        name: "show",
+        aliases: &[],
+        inputs: ":thing",
+        description: "writes a value on a line of output, a list with brackets",
+        token: Token::Show,
+    },
+    Keyword {
+        name: "type",
+        aliases: &[],
+        inputs: ":thing",
+        description: "writes a value to the output, without ending the line",
+        token: Token::Type,
+    },
+    Keyword {
+        name: "run",
+        aliases: &[],
+        inputs: ":list",
+        description: "runs a list as code",
+        token: Token::Run,
+    },
+    Keyword {
+        name: "apply",
+        aliases: &[],
+        inputs: ":template :inputs",
+        description: "runs a template, a procedure or a primitive on a list of inputs",
+        token: Token::Apply,
+    },
+    Keyword {
+        name: "map",
+        aliases: &[],
+        inputs: ":template :list",
+        description: "reports the list of what a template makes of each item",
+        token: Token::Map,
+    },
+    Keyword {
+        name: "filter",
+        aliases: &[],
+        inputs: ":template :list",
+        description: "reports the items a template is true for",
+        token: Token::Filter,
+    },
+    Keyword {
+        name: "reduce",
+        aliases: &[],
+        inputs: ":template :list",
+        description: "combines the items with a template of two inputs, from the right",
+        token: Token::Reduce,
+    },
+    Keyword {
+        name: "foreach",
+        aliases: &[],
+        inputs: ":list :template",
+        description: "runs a template for each item of a list",
+        token: Token::Foreach,
+    },
+    Keyword {
+        name: "catch",
+        aliases: &[],
+        inputs: ":tag [block]",
+        description: "runs a block, stopping there when it throws `tag`",
+        token: Token::Catch,
+    },
+    Keyword {
+        name: "throw",
+        aliases: &[],
+        inputs: ":tag :value?",
+        description: "stops back at the CATCH of `tag`, with an optional value",
+        token: Token::Throw,
+    },
+    Keyword {
+        name: "error",
+        aliases: &[],
+        inputs: "",
+        description:
+            "reports the error caught by CATCH \"error, as a list of message, procedure and line",
+        token: Token::Error,
+    },
+    Keyword {
+        name: "repcount",
+        aliases: &["#"],
+        inputs: "",
+        description: "reports the round of the innermost REPEAT, from 1",
+        token: Token::Repcount,
+    },
+    Keyword {
+        name: "first",
+        aliases: &[],
+        inputs: ":thing",
+        description: "reports the first item of a list, or character of a word",
+        token: Token::Primitive(Primitive::First),
+    },
+    Keyword {
+        name: "butfirst",
+        aliases: &["bf"],
+        inputs: ":thing",
+        description: "reports a list or word without its first item",
+        token: Token::Primitive(Primitive::Butfirst),
+    },
+    Keyword {
+        name: "last",
+        aliases: &[],
+        inputs: ":thing",
+        description: "reports the last item of a list, or character of a word",
+        token: Token::Primitive(Primitive::Last),
+    },
+    Keyword {
+        name: "item",
+        aliases: &[],
+        inputs: ":index :thing",
+        description: "reports the item of a list or word at `index`, from 1",
+        token: Token::Primitive(Primitive::Item),
+    },
+    Keyword {
+        name: "fput",
+        aliases: &[],
+        inputs: ":thing :list",
+        description: "reports a list with `thing` added at the front",
+        token: Token::Primitive(Primitive::Fput),
+    },
+    Keyword {
+        name: "lput",
+        aliases: &[],
+        inputs: ":thing :list",
+        description: "reports a list with `thing` added at the end",
+        token: Token::Primitive(Primitive::Lput),
+    },
+    Keyword {
+        name: "count",
+        aliases: &[],
+        inputs: ":thing",
+        description: "reports the number of items of a list, or characters of a word",
+        token: Token::Primitive(Primitive::Count),
+    },
+    Keyword {
+        name: "word",
+        aliases: &[],
+        inputs: ":a :b",
+        description: "reports two words joined into one",
+        token: Token::Primitive(Primitive::Word),
+    },
+    Keyword {
+        name: "sentence",
+        aliases: &["se"],
+        inputs: ":a :b",
+        description: "reports a list of the items of both inputs",
+        token: Token::Primitive(Primitive::Sentence),
+    },
+    Keyword {
+        name: "emptyp",
+        aliases: &[],
+        inputs: ":thing",
+        description: "reports whether a list or word is empty",
+        token: Token::Primitive(Primitive::Emptyp),
+    },
+    Keyword {
+        name: "memberp",
+        aliases: &[],
+        inputs: ":thing :list",
+        description: "reports whether `thing` is an item of a list",
+        token: Token::Primitive(Primitive::Memberp),
+    },
+    Keyword {
+        name: "list",
+        aliases: &[],
+        inputs: ":a :b",
+        description: "reports a list of the two inputs",
+        token: Token::Primitive(Primitive::List),
+    },
+    Keyword {
+        name: "repeat",
+        aliases: &["rp"],
+        inputs: ":count [block]",
+        description: "runs a block `count` times",
+        token: Token::Repeat,
+    },
+    Keyword {
+        name: "if",
+        aliases: &[],
+        inputs: ":condition [block]",
+        description: "runs a block when the condition is true",
+        token: Token::If,
+    },
+    Keyword {
+        name: "to",
+        aliases: &[],
+        inputs: "name :inputs",
+        description: "defines a procedure, up to END",
+        token: Token::To,
+    },
+    Keyword {
+        name: "end",
+        aliases: &[],
+        inputs: "",
+        description: "ends a procedure",
+        token: Token::End,
+    },
+];
+
+impl Keyword {
+    /// The keyword of this full name
+    pub fn named(name: &str) -> Option<&'static Self> {
+        KEYWORDS.iter().find(|keyword| keyword.name == name)
+    }
+}
+
+fn keyword(text: &str) -> Token {
+    let lower = text.to_lowercase();
+    if let Some(keyword) = KEYWORDS
+        .iter()
+        .find(|keyword| keyword.name == lower || keyword.aliases.contains(&lower.as_str()))
+    {
+        return keyword.token.clone();
+    }
+    match text {
+        "[" => Token::LBracket,
+        "]" => Token::RBracket,
+        ">" => Token::Gtr,
+        "<" => Token::Less,
+        "==" => Token::Eq,
+        "!=" => Token::Neq,
+        "+" => Token::Add,
+        "-" => Token::Sub,
+        "*" => Token::Mul,
+        "/" => Token::Div,
+        _ => text.parse::<f32>().map_or_else(
+            |_| {
+                let string = String::from(text);
+                match string.chars().next().expect("string cannot be empty") {
+                    ':' | '?' => Token::Var(string),
+                    '"' => Token::Word(string[1..].to_string()),
+                    _ => Token::Ident(string),
+                }
+            },
+            Token::Number,
+        ),
+    }
+}
+
+/// Keywords a procedure may take the name of
+fn is_shadowable(token: &Token) -> bool {
+    !is_control(token)
+        && !matches!(
+            token,
+            Token::Ident(_)
+                | Token::Var(_)
+                | Token::Word(_)
+                | Token::Number(_)
+                | Token::LBracket
+                | Token::RBracket
+                | Token::Gtr
+                | Token::Less
+                | Token::Eq
+                | Token::Neq
+                | Token::Add
+                | Token::Sub
+                | Token::Mul
+                | Token::Div
+                | Token::Repcount
+        )
+}
+
+/// Keywords the shape of the code rests on, which no procedure may be named
+fn is_control(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::To
+            | Token::End
+            | Token::If
+            | Token::Repeat
+            | Token::Catch
+            | Token::Throw
+            | Token::Ask
+    )
+}
+
+/// Names are the same whatever their case
+pub fn same(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+/// Collects `TO` signatures ahead of parsing, so that calls take exactly as
+/// many inputs as declared, wherever the procedure is defined. Warns about
+/// procedures defined twice, and about those shadowing a primitive.
+fn declarations(
+    tokens: &VecDeque<Token>,
+    spans: &[Span],
+    defining: &BTreeSet<usize>,
+    locale: &Locale,
+) -> (Signatures, Vec<Diagnostic>) {
+    let mut procs = Signatures::new();
+    let mut defined: HashMap<String, Span> = HashMap::new();
+    let mut warnings = vec![];
+    for &i in defining {
+        let name = match (&tokens[i], tokens.get(i + 1)) {
+            (Token::To, Some(Token::Ident(name))) => name,
+            _ => continue,
+        };
+        let span = spans[i + 1].clone();
+        let shadowed = keyword(locale.keyword(name).unwrap_or(name));
+        if is_shadowable(&shadowed) {
+            warnings.push(Diagnostic {
+                message: format!(
+                    "procedure `{name}` shadows the primitive `{}`",
+                    token_word(&shadowed)
+                ),
+                span: span.clone(),
+                related: None,
+            });
+        }
+        if let Some(previous) = defined.insert(name.to_lowercase(), span.clone()) {
+            warnings.push(Diagnostic {
+                message: format!("procedure `{name}` is defined again, replacing the earlier one"),
+                span,
+                related: Some(previous),
+            });
+        }
+        let inputs = tokens
+            .iter()
+            .skip(i + 2)
+            .take_while(|token| matches!(token, Token::Var(_)))
+            .count();
+        procs.insert(name.to_lowercase(), inputs);
+    }
+    (procs, warnings)
+}
+
+fn to_ast(
+    tokens: &mut Tokens,
+    spans: &[Span],
+    procs: &Signatures,
+) -> Result<Vec<Expression>, LogoError> {
     let mut stack: VecDeque<Token> = VecDeque::new();
-    let exps = build(tokens, &mut stack);
+    let exps = build(tokens, &mut stack, spans, procs)?;
     match stack.pop_back() {
-        Some(Token::LBracket) => Err(LogoError::SyntaxError {
-            err: "Expected closing token ']'.".into(),
-            tokens: tokens.clone(),
-        }),
-        Some(Token::To) => Err(LogoError::SyntaxError {
-            err: "Expected closing token 'end'.".into(),
-            tokens: tokens.clone(),
-        }),
-        _ => exps,
+        Some(Token::LBracket) => Err(syntax_error(Expected::Close, None, tokens)),
+        Some(Token::To) => Err(syntax_error(Expected::End, None, tokens)),
+        _ => Ok(exps),
     }
 }
 
 fn build(
-    tokens: &mut VecDeque<Token>,
+    tokens: &mut Tokens,
     stack: &mut VecDeque<Token>,
+    spans: &[Span],
+    procs: &Signatures,
 ) -> Result<Vec<Expression>, LogoError> {
     let mut exps = vec![];
 
     while let Some(next) = tokens.pop_front() {
+        let (first, at) = (spans.len() - tokens.len() - 1, exps.len());
         match next {
             Token::Clear => exps.push(Expression::Clear),
             Token::Setpos => exps.push(Expression::Setpos(
@@ -166,6 +1135,42 @@ fn build(
             Token::Setpensize => {
                 exps.push(Expression::Setpensize(Box::new(build_arg(tokens)?)));
             }
+            Token::Showturtle => exps.push(Expression::Showturtle),
+            Token::Hideturtle => exps.push(Expression::Hideturtle),
+            Token::Setshape => exps.push(build_shape(tokens)?),
+            Token::Stamp => exps.push(Expression::Stamp),
+            Token::Fence => exps.push(Expression::Fence),
+            Token::Window => exps.push(Expression::Window),
+            Token::Tell => exps.push(Expression::Tell(build_turtles(tokens)?)),
+            Token::Ask => exps.push(Expression::Ask(
+                build_turtles(tokens)?,
+                build_block(tokens, stack, spans, procs)?,
+            )),
+            Token::Setturtle => {
+                exps.push(Expression::Setturtle(Box::new(build_arg(tokens)?)));
+            }
+            Token::Setspeed => {
+                exps.push(Expression::Setspeed(Box::new(build_arg(tokens)?)));
+            }
+            Token::Wait => exps.push(Expression::Wait(Box::new(build_arg(tokens)?))),
+            Token::Label => exps.push(Expression::Label(Box::new(build_arg(tokens)?))),
+            Token::Print => exps.push(Expression::Print(Box::new(build_arg(tokens)?))),
+            Token::Show => exps.push(Expression::Show(Box::new(build_arg(tokens)?))),
+            Token::Type => exps.push(Expression::Type(Box::new(build_arg(tokens)?))),
+            Token::Run => exps.push(Expression::Run(Box::new(build_arg(tokens)?))),
+            Token::Apply => exps.push(Expression::Apply(
+                Box::new(build_arg(tokens)?),
+                Box::new(build_arg(tokens)?),
+            )),
+            Token::Foreach => exps.push(Expression::Foreach(
+                Box::new(build_arg(tokens)?),
+                Box::new(build_arg(tokens)?),
+            )),
+            Token::Catch => exps.push(Expression::Catch(
+                Box::new(build_arg(tokens)?),
+                build_block(tokens, stack, spans, procs)?,
+            )),
+            Token::Throw => exps.push(build_throw(tokens)?),
             Token::Forward => {
                 exps.push(Expression::Forward(Box::new(build_arg(tokens)?)));
             }
@@ -185,94 +1190,232 @@ fn build(
             Token::Circle => {
                 exps.push(Expression::Circle(Box::new(build_arg(tokens)?)));
             }
-            Token::Repeat => exps.push(build_repeat(tokens, stack)?),
-            Token::If => exps.push(build_if(tokens, stack)?),
-            Token::To => exps.push(build_to(tokens, stack)?),
-            Token::Ident(x) => exps.push(build_call(tokens, x)?),
+            Token::Repeat => exps.push(build_repeat(tokens, stack, spans, procs)?),
+            Token::If => exps.push(build_if(tokens, stack, spans, procs)?),
+            Token::To => exps.push(build_to(tokens, stack, spans, procs)?),
+            Token::Ident(x) => exps.push(build_call(tokens, x, &spans[first], procs)?),
             Token::RBracket => {
-                pop_stack(&Token::LBracket, &Token::RBracket, stack)?;
+                pop_stack(&Token::LBracket, tokens, stack)?;
                 break;
             }
             Token::End => {
-                pop_stack(&Token::To, &Token::End, stack)?;
+                pop_stack(&Token::To, tokens, stack)?;
                 break;
             }
             _ => {
-                return Err(LogoError::SyntaxError {
-                    err: format!("Unexpected token '{next:?}'"),
-                    tokens: tokens.clone(),
-                })
+                return Err(syntax_error(
+                    Expected::Statement,
+                    Some(tokens.taken()),
+                    tokens,
+                ))
             }
         };
+        let last = &spans[spans.len() - tokens.len() - 1];
+        let span = Span {
+            end: last.end,
+            ..spans[first].clone()
+        };
+        exps.insert(at, Expression::Source(span));
     }
 
     Ok(exps)
 }
 
 fn build_repeat(
-    tokens: &mut VecDeque<Token>,
+    tokens: &mut Tokens,
     stack: &mut VecDeque<Token>,
+    spans: &[Span],
+    procs: &Signatures,
 ) -> Result<Expression, LogoError> {
     let count = Box::new(build_arg(tokens)?);
-    stack.push_back(Token::LBracket);
-    match tokens.pop_front() {
-        Some(Token::LBracket) => Ok(Expression::Repeat(count, build(tokens, stack)?)),
-        Some(other) => Err(LogoError::SyntaxError {
-            err: format!("Unexpected token '{other:?}'. Expected '['"),
-            tokens: tokens.clone(),
-        }),
-        None => Err(LogoError::SyntaxError {
-            err: "Expected '[', got nothing.".into(),
-            tokens: tokens.clone(),
-        }),
-    }
+    Ok(Expression::Repeat(
+        count,
+        build_block(tokens, stack, spans, procs)?,
+    ))
 }
 
 fn build_if(
-    tokens: &mut VecDeque<Token>,
+    tokens: &mut Tokens,
     stack: &mut VecDeque<Token>,
+    spans: &[Span],
+    procs: &Signatures,
 ) -> Result<Expression, LogoError> {
     let condition = Box::new(build_condition(tokens)?);
-    stack.push_back(Token::LBracket);
+    Ok(Expression::If(
+        condition,
+        build_block(tokens, stack, spans, procs)?,
+    ))
+}
+
+fn build_block(
+    tokens: &mut Tokens,
+    stack: &mut VecDeque<Token>,
+    spans: &[Span],
+    procs: &Signatures,
+) -> Result<Vec<Expression>, LogoError> {
     match tokens.pop_front() {
-        Some(Token::LBracket) => Ok(Expression::If(condition, build(tokens, stack)?)),
-        Some(other) => Err(LogoError::SyntaxError {
-            err: format!("Unexpected token '{other:?}'. Expected '['"),
-            tokens: tokens.clone(),
-        }),
-        None => Err(LogoError::SyntaxError {
-            err: "Expected '[', got nothing.".into(),
-            tokens: tokens.clone(),
-        }),
+        Some(Token::LBracket) => {
+            stack.push_back(Token::LBracket);
+            build(tokens, stack, spans, procs)
+        }
+        Some(_) => Err(syntax_error(Expected::Block, Some(tokens.taken()), tokens)),
+        None => Err(syntax_error(Expected::Block, None, tokens)),
+    }
+}
+
+/// A single turtle id, or a bracketed list of them
+fn build_turtles(tokens: &mut Tokens) -> Result<Vec<Expression>, LogoError> {
+    if tokens.front() != Some(&Token::LBracket) {
+        return Ok(vec![build_arg(tokens)?]);
+    }
+    tokens.pop_front();
+    let mut ids = vec![];
+    loop {
+        match tokens.front() {
+            Some(Token::RBracket) => {
+                tokens.pop_front();
+                return Ok(ids);
+            }
+            Some(_) => ids.push(build_arg(tokens)?),
+            None => return Err(syntax_error(Expected::Close, None, tokens)),
+        }
     }
 }
 
 fn build_to(
-    tokens: &mut VecDeque<Token>,
+    tokens: &mut Tokens,
     stack: &mut VecDeque<Token>,
+    spans: &[Span],
+    procs: &Signatures,
 ) -> Result<Expression, LogoError> {
-    let ident = build_name(tokens)?;
+    let ident = build_name(tokens, spans)?;
     stack.push_back(Token::To);
     let mut args = vec![];
-    while let Some(Token::Var(x)) = tokens.get(0) {
+    while let Some(Token::Var(x)) = tokens.front() {
         args.push(x.to_string());
         tokens.pop_front();
     }
-    Ok(Expression::To(ident, args, build(tokens, stack)?))
+    Ok(Expression::To(
+        ident,
+        args,
+        build(tokens, stack, spans, procs)?,
+    ))
 }
 
-fn build_var(tokens: &mut VecDeque<Token>) -> Result<Expression, LogoError> {
+fn build_var(tokens: &mut Tokens) -> Result<Expression, LogoError> {
     match tokens.pop_front() {
         Some(Token::Number(x)) => Ok(Expression::Number(x)),
         Some(Token::Var(x)) => Ok(Expression::Var(x)),
-        x => Err(LogoError::SyntaxError {
-            err: format!("no such variable: {x:?}"),
-            tokens: tokens.clone(),
-        }),
+        Some(Token::Word(x)) => Ok(Expression::Word(x)),
+        Some(Token::LBracket) => Ok(Expression::List(build_list(tokens)?)),
+        Some(Token::Who) => Ok(Expression::Who),
+        Some(Token::Repcount) => Ok(Expression::Repcount),
+        Some(Token::Error) => Ok(Expression::Error),
+        Some(Token::Primitive(p)) => build_primitive(tokens, p),
+        Some(Token::Map) => Ok(Expression::Map(
+            Box::new(build_arg(tokens)?),
+            Box::new(build_arg(tokens)?),
+        )),
+        Some(Token::Filter) => Ok(Expression::Filter(
+            Box::new(build_arg(tokens)?),
+            Box::new(build_arg(tokens)?),
+        )),
+        Some(Token::Reduce) => Ok(Expression::Reduce(
+            Box::new(build_arg(tokens)?),
+            Box::new(build_arg(tokens)?),
+        )),
+        Some(_) => Err(syntax_error(Expected::Input, Some(tokens.taken()), tokens)),
+        None => Err(syntax_error(Expected::Input, None, tokens)),
+    }
+}
+
+/// A tag, and the value thrown with it if one follows
+fn build_throw(tokens: &mut Tokens) -> Result<Expression, LogoError> {
+    let tag = Box::new(build_arg(tokens)?);
+    let value = match tokens.front() {
+        Some(token) if starts_operand(token) => Some(Box::new(build_arg(tokens)?)),
+        _ => None,
+    };
+    Ok(Expression::Throw(tag, value))
+}
+
+fn build_primitive(tokens: &mut Tokens, p: Primitive) -> Result<Expression, LogoError> {
+    let span = tokens.taken_span().clone();
+    let mut inputs = vec![];
+    for given in 0..p.arity() {
+        if !matches!(tokens.front(), Some(token) if starts_operand(token)) {
+            return Err(LogoError::ArityMismatch {
+                name: p.name().into(),
+                expected: p.arity(),
+                got: given,
+                span: Some(span),
+            });
+        }
+        inputs.push(build_arg(tokens)?);
     }
+    Ok(Expression::Primitive(p, inputs))
 }
 
-fn build_math(tokens: &mut VecDeque<Token>, op: Box<Expression>) -> Result<Expression, LogoError> {
+fn starts_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Number(_)
+            | Token::Var(_)
+            | Token::Word(_)
+            | Token::LBracket
+            | Token::Who
+            | Token::Repcount
+            | Token::Error
+            | Token::Primitive(_)
+            | Token::Map
+            | Token::Filter
+            | Token::Reduce
+    )
+}
+
+/// A list literal, taken as data: nothing in it is evaluated, and its words
+/// are kept as written
+fn build_list(tokens: &mut Tokens) -> Result<Vec<Value>, LogoError> {
+    let mut items = vec![];
+    loop {
+        match tokens.pop_front() {
+            Some(Token::RBracket) => return Ok(items),
+            Some(Token::LBracket) => items.push(Value::List(build_list(tokens)?)),
+            Some(Token::Number(n)) => items.push(Value::Number(n)),
+            Some(_) => items.push(Value::Word(tokens.taken().to_string())),
+            None => return Err(syntax_error(Expected::Close, None, tokens)),
+        }
+    }
+}
+
+/// Spells a token back as a word
+fn token_word(token: &Token) -> String {
+    match token {
+        Token::Ident(x) | Token::Var(x) => x.clone(),
+        Token::Word(x) => format!("\"{x}"),
+        Token::Number(n) => n.to_string(),
+        Token::Primitive(p) => p.name().into(),
+        Token::LBracket => "[".into(),
+        Token::RBracket => "]".into(),
+        Token::Gtr => ">".into(),
+        Token::Less => "<".into(),
+        Token::Eq => "==".into(),
+        Token::Neq => "!=".into(),
+        Token::Add => "+".into(),
+        Token::Sub => "-".into(),
+        Token::Mul => "*".into(),
+        Token::Div => "/".into(),
+        keyword => KEYWORDS
+            .iter()
+            .find(|known| known.token == *keyword)
+            .map_or_else(
+                || format!("{keyword:?}").to_lowercase(),
+                |known| known.name.into(),
+            ),
+    }
+}
+
+fn build_math(tokens: &mut Tokens, op: Box<Expression>) -> Result<Expression, LogoError> {
     let lhs = Box::new(build_var(tokens)?);
     tokens.pop_front();
     let rhs = Box::new(build_var(tokens)?);
@@ -280,59 +1423,52 @@ fn build_math(tokens: &mut VecDeque<Token>, op: Box<Expression>) -> Result<Expre
     Ok(Expression::Math(lhs, op, rhs))
 }
 
-fn build_logical_op(tokens: &mut VecDeque<Token>) -> Result<Expression, LogoError> {
+fn build_logical_op(tokens: &mut Tokens) -> Result<Expression, LogoError> {
     match tokens.pop_front() {
         Some(Token::Less) => Ok(Expression::Less),
         Some(Token::Gtr) => Ok(Expression::Gtr),
         Some(Token::Eq) => Ok(Expression::Eq),
         Some(Token::Neq) => Ok(Expression::Neq),
-        Some(other) => Err(LogoError::SyntaxError {
-            err: format!("Unexpected token '{other:?}'. Expected logical operator."),
-            tokens: tokens.clone(),
-        }),
-        None => Err(LogoError::SyntaxError {
-            err: "Expected logical operator, got nothing.".into(),
-            tokens: tokens.clone(),
-        }),
+        Some(_) => Err(syntax_error(
+            Expected::Comparison,
+            Some(tokens.taken()),
+            tokens,
+        )),
+        None => Err(syntax_error(Expected::Comparison, None, tokens)),
     }
 }
 
-fn build_name(tokens: &mut VecDeque<Token>) -> Result<String, LogoError> {
+/// The name a `TO` gives its procedure. A primitive's name is taken as is,
+/// as it isn't shadowed until the `TO` is known to be a definition.
+fn build_name(tokens: &mut Tokens, spans: &[Span]) -> Result<String, LogoError> {
     match tokens.pop_front() {
         Some(Token::Ident(x)) => Ok(x),
-        Some(x) => Err(LogoError::SyntaxError {
-            err: format!("Unexpected token '{x:?}'. Expected identifier."),
-            tokens: tokens.clone(),
-        }),
-        None => Err(LogoError::SyntaxError {
-            err: "Expected identifier, got nothing.".into(),
-            tokens: tokens.clone(),
+        Some(token) if is_shadowable(&token) => Ok(tokens.taken().to_string()),
+        Some(token) if is_control(&token) => Err(LogoError::UnexpectedInput {
+            found: tokens.taken().to_string(),
+            expected: Expected::Unreserved,
+            span: spans[spans.len() - tokens.len() - 1].clone(),
         }),
+        Some(_) => Err(syntax_error(Expected::Name, Some(tokens.taken()), tokens)),
+        None => Err(syntax_error(Expected::Name, None, tokens)),
     }
 }
 
-fn pop_stack(open: &Token, close: &Token, stack: &mut VecDeque<Token>) -> Result<(), LogoError> {
-    stack.pop_back().map_or_else(
-        || {
-            Err(LogoError::SyntaxError {
-                err: format!("Expected opening token '{open:?}' before '{close:?}'."),
-                tokens: stack.clone(),
-            })
-        },
-        |token| {
-            if token.eq(open) {
-                Ok(())
-            } else {
-                Err(LogoError::SyntaxError {
-                    err: format!("Expected opening token '{open:?}' before '{close:?}'."),
-                    tokens: stack.clone(),
-                })
-            }
-        },
-    )
+/// Closes the innermost `[` or `TO`, which must be `open`, with the token
+/// just taken
+fn pop_stack(open: &Token, tokens: &Tokens, stack: &mut VecDeque<Token>) -> Result<(), LogoError> {
+    if stack.pop_back().as_ref() == Some(open) {
+        return Ok(());
+    }
+    let expected = if *open == Token::To {
+        Expected::To
+    } else {
+        Expected::Open
+    };
+    Err(syntax_error(expected, Some(tokens.taken()), stack))
 }
 
-fn build_arg(tokens: &mut VecDeque<Token>) -> Result<Expression, LogoError> {
+fn build_arg(tokens: &mut Tokens) -> Result<Expression, LogoError> {
     let op = tokens.get(1);
     match op {
         Some(Token::Add) => build_math(tokens, Box::new(Expression::Add)),
@@ -343,27 +1479,447 @@ fn build_arg(tokens: &mut VecDeque<Token>) -> Result<Expression, LogoError> {
     }
 }
 
-fn build_set_color(tokens: &mut VecDeque<Token>) -> Result<Expression, LogoError> {
+fn build_set_color(tokens: &mut Tokens) -> Result<Expression, LogoError> {
     let r = Box::new(build_arg(tokens)?);
     let g = Box::new(build_arg(tokens)?);
     let b = Box::new(build_arg(tokens)?);
     Ok(Expression::Setcolor(r, g, b))
 }
 
-fn build_condition(tokens: &mut VecDeque<Token>) -> Result<Expression, LogoError> {
-    Ok(Expression::Condition(
-        Box::new(build_arg(tokens)?),
-        Box::new(build_logical_op(tokens)?),
-        Box::new(build_arg(tokens)?),
-    ))
+/// A shape named whatever its case, as in `SETSHAPE TURTLE` or
+/// `setshape "arrow`, or a polygon
+fn build_shape(tokens: &mut Tokens) -> Result<Expression, LogoError> {
+    let shape = match tokens.pop_front() {
+        Some(Token::Circle) => Shape::Circle,
+        Some(Token::Ident(name) | Token::Word(name)) => match name.to_lowercase().as_str() {
+            "triangle" => Shape::Triangle,
+            "turtle" => Shape::Turtle,
+            "arrow" => Shape::Arrow,
+            _ => return Err(syntax_error(Expected::Shape, Some(tokens.taken()), tokens)),
+        },
+        Some(Token::LBracket) => Shape::Polygon(build_polygon(tokens)?),
+        Some(_) => return Err(syntax_error(Expected::Shape, Some(tokens.taken()), tokens)),
+        None => return Err(syntax_error(Expected::Shape, None, tokens)),
+    };
+    Ok(Expression::Setshape(shape))
 }
 
-fn build_call(tokens: &mut VecDeque<Token>, name: String) -> Result<Expression, LogoError> {
-    let mut args = vec![];
+fn build_polygon(tokens: &mut Tokens) -> Result<Vec<(f32, f32)>, LogoError> {
+    let mut coords = vec![];
+    loop {
+        match tokens.pop_front() {
+            Some(Token::Number(n)) => coords.push(n),
+            Some(Token::RBracket) => break,
+            Some(_) => {
+                return Err(syntax_error(
+                    Expected::Coordinate,
+                    Some(tokens.taken()),
+                    tokens,
+                ))
+            }
+            None => return Err(syntax_error(Expected::Close, None, tokens)),
+        }
+    }
+    if coords.len() < 6 || coords.len() % 2 != 0 {
+        return Err(syntax_error(Expected::Points, Some(tokens.taken()), tokens));
+    }
+    Ok(coords.chunks(2).map(|p| (p[0], p[1])).collect())
+}
+
+/// A comparison, or any expression that evaluates to true or false
+fn build_condition(tokens: &mut Tokens) -> Result<Expression, LogoError> {
+    let lhs = build_arg(tokens)?;
+    match tokens.front() {
+        Some(Token::Less | Token::Gtr | Token::Eq | Token::Neq) => Ok(Expression::Condition(
+            Box::new(lhs),
+            Box::new(build_logical_op(tokens)?),
+            Box::new(build_arg(tokens)?),
+        )),
+        _ => Ok(lhs),
+    }
+}
 
-    while let Some(Token::Var(_) | Token::Number(_)) = tokens.get(0) {
+/// A call takes as many inputs as its `TO` declares, and no more. Procedures
+/// unknown to the parser take whatever operands follow, and fail when run.
+fn build_call(
+    tokens: &mut Tokens,
+    name: String,
+    span: &Span,
+    procs: &Signatures,
+) -> Result<Expression, LogoError> {
+    let expected = procs.get(&name.to_lowercase()).copied();
+    let mut args = vec![];
+    while args.len() < expected.unwrap_or(usize::MAX)
+        && matches!(tokens.front(), Some(token) if starts_operand(token))
+    {
         args.push(build_arg(tokens)?);
     }
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Ok(Expression::Call(name, args)),
+    };
+    // operands left over are inputs it was given too many
+    let mut got = args.len();
+    while matches!(tokens.front(), Some(token) if starts_operand(token)) {
+        build_arg(tokens)?;
+        got += 1;
+    }
+    if got == expected {
+        Ok(Expression::Call(name, args))
+    } else {
+        Err(LogoError::ArityMismatch {
+            name,
+            expected,
+            got,
+            span: Some(span.clone()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logo::locale::{ENGLISH, HEBREW};
+
+    fn parse(code: &str, locale: &Locale) -> Vec<Expression> {
+        let (exps, _) = parse_with(code, &Signatures::new(), locale).expect("code must parse");
+        exps.into_iter()
+            .filter(|exp| !matches!(exp, Expression::Source(_)))
+            .collect()
+    }
+
+    fn printed_list(code: &str, locale: &Locale) -> Vec<Value> {
+        match parse(code, locale).as_slice() {
+            [Expression::Print(list)] => match list.as_ref() {
+                Expression::List(items) => items.clone(),
+                other => panic!("expected a list, got {other:?}"),
+            },
+            other => panic!("expected a PRINT, got {other:?}"),
+        }
+    }
 
-    Ok(Expression::Call(name, args))
+    fn words(items: &[Value]) -> Vec<String> {
+        items.iter().map(Value::printed).collect()
+    }
+
+    #[test]
+    fn list_keeps_keywords_as_written() {
+        let items = printed_list("print [I can go FD and CS too]", &ENGLISH);
+        assert_eq!(words(&items), ["I", "can", "go", "FD", "and", "CS", "too"]);
+    }
+
+    #[test]
+    fn list_keeps_localized_keywords_as_written() {
+        let items = printed_list("הדפס [שלום קד עולם]", &HEBREW);
+        assert_eq!(words(&items), ["שלום", "קד", "עולם"]);
+    }
+
+    fn shape(code: &str) -> Shape {
+        match parse(code, &ENGLISH).as_slice() {
+            [Expression::Setshape(shape)] => shape.clone(),
+            other => panic!("expected a SETSHAPE, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn shapes_are_named_whatever_their_case() {
+        assert_eq!(shape("setshape turtle"), Shape::Turtle);
+        assert_eq!(shape("SETSHAPE TURTLE"), Shape::Turtle);
+        assert_eq!(shape("SetShape Triangle"), Shape::Triangle);
+        assert_eq!(shape("setshape ARROW"), Shape::Arrow);
+        assert_eq!(shape("SETSHAPE CIRCLE"), Shape::Circle);
+    }
+
+    #[test]
+    fn uppercase_and_mixed_case_programs_parse() {
+        for code in [
+            "TO SQUARE :SIDE\nREPEAT 4 [FD :SIDE RT 90]\nEND\nSQUARE 10\nSETSHAPE TURTLE",
+            "To Square :Side\nRepeat 4 [Fd :side Rt 90]\nEnd\nsquare 10\nSetShape Turtle",
+        ] {
+            let exps = parse(code, &ENGLISH);
+            assert!(matches!(exps.as_slice(), [
+                Expression::To(..),
+                Expression::Call(_, args),
+                Expression::Setshape(Shape::Turtle),
+            ] if args.len() == 1));
+        }
+    }
+
+    #[test]
+    fn shapes_may_be_quoted_words() {
+        assert_eq!(shape("setshape \"turtle"), Shape::Turtle);
+        assert_eq!(shape("SETSHAPE \"Arrow"), Shape::Arrow);
+        assert!(parse_with("setshape \"hexagon", &Signatures::new(), &ENGLISH).is_err());
+    }
+
+    fn arity_error(code: &str) -> (usize, usize, Span) {
+        match parse_with(code, &Signatures::new(), &ENGLISH) {
+            Err(LogoError::ArityMismatch {
+                expected,
+                got,
+                span: Some(span),
+                ..
+            }) => (expected, got, span),
+            other => panic!("expected an arity mismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn calls_take_as_many_inputs_as_declared() {
+        let exps = parse("to sq :n\nfd :n\nend\nsq 10 fd 20", &ENGLISH);
+        assert!(matches!(exps.as_slice(), [
+            Expression::To(..),
+            Expression::Call(_, args),
+            Expression::Forward(_),
+        ] if args.len() == 1));
+    }
+
+    #[test]
+    fn calls_before_their_definition_know_its_inputs() {
+        let exps = parse("sq 10\nto sq :n\nfd :n\nend", &ENGLISH);
+        assert!(matches!(exps.as_slice(), [
+            Expression::Call(_, args),
+            Expression::To(..),
+        ] if args.len() == 1));
+        let (expected, got, span) = arity_error("fd 10\n  sq\nto sq :n\nfd :n\nend");
+        assert_eq!((expected, got), (1, 0));
+        assert_eq!((span.line, span.column), (2, 3));
+    }
+
+    #[test]
+    fn too_few_inputs_point_at_the_call() {
+        let (expected, got, span) = arity_error("to sq :a :b\nend\nrt 90 sq 10\nfd 10");
+        assert_eq!((expected, got), (2, 1));
+        assert_eq!((span.line, span.column), (3, 7));
+        // primitives point at themselves too
+        let (expected, got, span) = arity_error("fd 10\nprint item 2");
+        assert_eq!((expected, got), (2, 1));
+        assert_eq!((span.line, span.column), (2, 7));
+    }
+
+    #[test]
+    fn too_many_inputs_are_counted() {
+        let (expected, got, span) = arity_error("to sq :a\nend\nsq 1 2 3");
+        assert_eq!((expected, got), (1, 3));
+        assert_eq!((span.line, span.column), (3, 1));
+    }
+
+    #[test]
+    fn unknown_procedures_take_every_operand() {
+        let exps = parse("mystery 1 2 [3]", &ENGLISH);
+        assert!(matches!(exps.as_slice(), [Expression::Call(_, args)] if args.len() == 3));
+    }
+
+    #[test]
+    fn emoji_programs_parse_in_the_default_locale() {
+        let exps = parse("🔁 4 [⬆️ 10 ➡ 90]", &ENGLISH);
+        assert!(
+            matches!(exps.as_slice(), [Expression::Repeat(_, body)] if matches!(
+                body.as_slice(),
+                [Expression::Source(_), Expression::Forward(_), Expression::Source(_), Expression::Right(_)]
+            ))
+        );
+    }
+
+    #[test]
+    fn list_keeps_numbers_and_nested_lists() {
+        let items = printed_list("print [1 [Repeat 2] \"x :y]", &ENGLISH);
+        assert!(matches!(items[0], Value::Number(n) if n == 1.0));
+        assert!(matches!(&items[1], Value::List(inner) if words(inner) == ["Repeat", "2"]));
+        assert_eq!(words(&items[2..]), ["\"x", ":y"]);
+    }
+
+    fn lexed(code: &str) -> Vec<&str> {
+        lex(code)
+            .expect("code must lex")
+            .into_iter()
+            .map(|(_, text)| text)
+            .collect()
+    }
+
+    fn unlexed(code: &str) -> (String, Expected, Span) {
+        match lex(code) {
+            Err(LogoError::UnexpectedInput {
+                found,
+                expected,
+                span,
+            }) => (found, expected, span),
+            other => panic!("expected unexpected input, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn lists_take_any_run_as_a_word() {
+        let items = printed_list("print [hello, world]", &ENGLISH);
+        assert_eq!(words(&items), ["hello,", "world"]);
+        let items = printed_list("print [the 5th of may. 10-5 a.b [x,y]]", &ENGLISH);
+        assert_eq!(
+            words(&items),
+            ["the", "5th", "of", "may.", "10-5", "a.b", "x,y"]
+        );
+    }
+
+    #[test]
+    fn lists_still_split_into_tokens_where_they_can() {
+        assert_eq!(
+            lexed("[fd :x*2 rt 90]"),
+            ["[", "fd", ":x", "*", "2", "rt", "90", "]"]
+        );
+        assert_eq!(lexed("[a;b]\nc"), ["[", "a", ";b]", "c"]);
+    }
+
+    #[test]
+    fn words_in_lists_keep_where_they_are() {
+        let spans: Vec<(usize, usize, usize)> = lex("print [\n  hello, world]")
+            .unwrap()
+            .into_iter()
+            .map(|(span, _)| (span.line, span.column, span.end - span.start))
+            .collect();
+        assert_eq!(
+            spans,
+            [(1, 1, 5), (1, 7, 1), (2, 3, 6), (2, 10, 5), (2, 15, 1)]
+        );
+    }
+
+    #[test]
+    fn outside_lists_what_does_not_lex_is_an_error() {
+        let (found, expected, span) = unlexed("fd 10\nprint hello, world");
+        assert_eq!((found.as_str(), expected), (",", Expected::Token));
+        assert_eq!((span.line, span.column), (2, 12));
+        let (found, expected, _) = unlexed("fd 5th");
+        assert_eq!((found.as_str(), expected), ("5th", Expected::Number));
+    }
+
+    #[test]
+    fn subtracting_takes_spaces() {
+        let (found, expected, span) = unlexed("fd 10-5");
+        assert_eq!((found.as_str(), expected), ("10-5", Expected::Spaces));
+        assert_eq!((span.line, span.column), (1, 4));
+        let (found, _, _) = unlexed("to f :x\nfd :x-5\nend");
+        assert_eq!(found, ":x-5");
+        assert_eq!(lexed("fd 10 - 5"), ["fd", "10", "-", "5"]);
+        assert_eq!(lexed("fd -5 rt -90"), ["fd", "-5", "rt", "-90"]);
+        assert_eq!(lexed("fd 10 -5"), ["fd", "10", "-5"]);
+    }
+
+    fn warnings(code: &str, locale: &Locale) -> Vec<(String, usize, Option<usize>)> {
+        let (_, warnings) = parse_with(code, &Signatures::new(), locale).expect("code must parse");
+        warnings
+            .into_iter()
+            .map(|d| (d.message, d.span.line, d.related.map(|span| span.line)))
+            .collect()
+    }
+
+    #[test]
+    fn procedures_defined_again_point_at_the_earlier_one() {
+        let found = warnings("to sq\nfd 1\nend\nto SQ :n\nfd :n\nend\nsq 5", &ENGLISH);
+        assert_eq!(
+            found,
+            [(
+                "procedure `SQ` is defined again, replacing the earlier one".into(),
+                4,
+                Some(1)
+            )]
+        );
+    }
+
+    #[test]
+    fn procedures_named_like_a_primitive_shadow_it() {
+        let found = warnings("to fd :n\nrt :n\nend\nfd 90", &ENGLISH);
+        assert_eq!(
+            found,
+            [(
+                "procedure `fd` shadows the primitive `forward`".into(),
+                1,
+                None
+            )]
+        );
+        let found = warnings("למד קד :n\nימ :n\nסוף\nקד 90", &HEBREW);
+        assert_eq!(found[0].0, "procedure `קד` shadows the primitive `forward`");
+    }
+
+    #[test]
+    fn to_in_a_list_defines_nothing() {
+        let code = "print [to fd]\nfd 10";
+        assert!(matches!(
+            parse(code, &ENGLISH).as_slice(),
+            [Expression::Print(_), Expression::Forward(_)]
+        ));
+        assert!(warnings(code, &ENGLISH).is_empty());
+    }
+
+    #[test]
+    fn to_in_a_block_defines_a_procedure() {
+        let code = "repeat 1 [to fd :a :b\nrt :a + :b\nend]\nfd 45 45";
+        assert!(matches!(
+            parse(code, &ENGLISH).as_slice(),
+            [Expression::Repeat(..), Expression::Call(name, args)] if name == "fd" && args.len() == 2
+        ));
+        let found = warnings(code, &ENGLISH);
+        assert_eq!(found[0].0, "procedure `fd` shadows the primitive `forward`");
+    }
+
+    #[test]
+    fn control_keywords_cannot_name_procedures() {
+        for (code, found) in [("to if :x\nend", "if"), ("fd 1\nto REPEAT\nend", "REPEAT")] {
+            let err = parse_with(code, &Signatures::new(), &ENGLISH).unwrap_err();
+            assert!(matches!(
+                &err,
+                LogoError::UnexpectedInput { found: text, expected: Expected::Unreserved, span }
+                    if text == found && span.column == 4
+            ));
+        }
+        let err = parse_with("למד אם\nסוף", &Signatures::new(), &HEBREW).unwrap_err();
+        assert!(matches!(
+            err,
+            LogoError::UnexpectedInput {
+                expected: Expected::Unreserved,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn comments_run_to_the_end_of_the_line() {
+        let code = "fd 10 ; forward [ not a list\n; a whole line\nrt 90";
+        assert!(matches!(
+            parse(code, &ENGLISH).as_slice(),
+            [Expression::Forward(_), Expression::Right(_)]
+        ));
+        let comments: Vec<(usize, usize)> = comments(code)
+            .unwrap()
+            .iter()
+            .map(|span| (span.line, span.column))
+            .collect();
+        assert_eq!(comments, [(1, 7), (2, 1)]);
+    }
+
+    #[test]
+    fn a_tilde_ending_a_line_joins_it_with_the_next() {
+        let code = "repeat 4 ~\n  [fd 10 rt 90]";
+        assert!(matches!(
+            parse(code, &ENGLISH).as_slice(),
+            [Expression::Repeat(_, body)]
+                if body.iter().filter(|exp| !matches!(exp, Expression::Source(_))).count() == 2
+        ));
+        // a comment continued onto the next line takes it along
+        let code = "fd 10 ; a comment ~\nthat goes on\nrt 90";
+        assert_eq!(
+            lexed(code),
+            ["fd", "10", "; a comment ~\nthat goes on", "rt", "90"]
+        );
+        assert!(matches!(
+            parse(code, &ENGLISH).as_slice(),
+            [Expression::Forward(_), Expression::Right(_)]
+        ));
+    }
+
+    #[test]
+    fn names_keep_their_case() {
+        let (tokens, _) = tokenize("to Square :Side\nFD :Side\nend\nSQUARE 10", &ENGLISH).unwrap();
+        assert_eq!(tokens[1], Token::Ident("Square".into()));
+        assert_eq!(tokens[2], Token::Var(":Side".into()));
+        assert_eq!(tokens[3], Token::Forward);
+        assert_eq!(tokens[6], Token::Ident("SQUARE".into()));
+    }
 }
@@ -0,0 +1,223 @@
+#![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_precision_loss)]
+#![allow(clippy::cast_sign_loss)]
+
+use super::parser::Primitive;
+use super::value::Value;
+use super::{Kind, LogoError};
+
+/// Applies a data primitive to its already evaluated inputs
+pub fn apply(p: Primitive, inputs: &[Value]) -> Result<Value, LogoError> {
+    let bad_input = |v: &Value| LogoError::BadInput(p.name().into(), v.to_string());
+    match (p, inputs) {
+        (Primitive::First, [thing]) => match thing {
+            Value::List(items) => items.first().cloned().ok_or_else(|| bad_input(thing)),
+            _ => chars(thing)?
+                .first()
+                .map(|c| Value::Word(c.to_string()))
+                .ok_or_else(|| bad_input(thing)),
+        },
+        (Primitive::Last, [thing]) => match thing {
+            Value::List(items) => items.last().cloned().ok_or_else(|| bad_input(thing)),
+            _ => chars(thing)?
+                .last()
+                .map(|c| Value::Word(c.to_string()))
+                .ok_or_else(|| bad_input(thing)),
+        },
+        (Primitive::Butfirst, [thing]) => match thing {
+            Value::List(items) if !items.is_empty() => Ok(Value::List(items[1..].to_vec())),
+            Value::List(_) => Err(bad_input(thing)),
+            _ => {
+                let chars = chars(thing)?;
+                if chars.is_empty() {
+                    return Err(bad_input(thing));
+                }
+                Ok(Value::Word(chars[1..].iter().collect()))
+            }
+        },
+        (Primitive::Item, [index, thing]) => {
+            let n = index.number()?;
+            if n < 1.0 || n.fract() != 0.0 {
+                return Err(bad_input(index));
+            }
+            let i = n as usize - 1;
+            match thing {
+                Value::List(items) => items.get(i).cloned().ok_or_else(|| bad_input(index)),
+                _ => chars(thing)?
+                    .get(i)
+                    .map(|c| Value::Word(c.to_string()))
+                    .ok_or_else(|| bad_input(index)),
+            }
+        }
+        (Primitive::Fput, [thing, Value::List(items)]) => {
+            let mut items = items.clone();
+            items.insert(0, thing.clone());
+            Ok(Value::List(items))
+        }
+        (Primitive::Lput, [thing, Value::List(items)]) => {
+            let mut items = items.clone();
+            items.push(thing.clone());
+            Ok(Value::List(items))
+        }
+        (Primitive::Fput, [thing, word]) => Ok(Value::Word(format!(
+            "{}{}",
+            chars(thing)?.iter().collect::<String>(),
+            chars(word)?.iter().collect::<String>()
+        ))),
+        (Primitive::Lput, [thing, word]) => Ok(Value::Word(format!(
+            "{}{}",
+            chars(word)?.iter().collect::<String>(),
+            chars(thing)?.iter().collect::<String>()
+        ))),
+        (Primitive::Count, [thing]) => Ok(Value::Number(match thing {
+            Value::List(items) => items.len(),
+            _ => chars(thing)?.len(),
+        } as f32)),
+        (Primitive::Word, [a, b]) => Ok(Value::Word(format!(
+            "{}{}",
+            chars(a)?.iter().collect::<String>(),
+            chars(b)?.iter().collect::<String>()
+        ))),
+        (Primitive::Sentence, [a, b]) => {
+            let mut items = vec![];
+            for thing in [a, b] {
+                match thing {
+                    Value::List(inner) => items.extend(inner.iter().cloned()),
+                    _ => items.push(thing.clone()),
+                }
+            }
+            Ok(Value::List(items))
+        }
+        (Primitive::List, [a, b]) => Ok(Value::List(vec![a.clone(), b.clone()])),
+        (Primitive::Emptyp, [thing]) => Ok(Value::Bool(match thing {
+            Value::List(items) => items.is_empty(),
+            _ => chars(thing)?.is_empty(),
+        })),
+        (Primitive::Memberp, [thing, Value::List(items)]) => {
+            Ok(Value::Bool(items.iter().any(|item| item.same(thing))))
+        }
+        (Primitive::Memberp, [thing, word]) => {
+            let needle = chars(thing)?;
+            Ok(Value::Bool(
+                needle.len() == 1 && chars(word)?.contains(&needle[0]),
+            ))
+        }
+        (_, inputs) => Err(LogoError::ArityMismatch {
+            name: p.name().into(),
+            expected: p.arity(),
+            got: inputs.len(),
+            span: None,
+        }),
+    }
+}
+
+/// The characters of a word. Numbers and booleans are words too.
+fn chars(thing: &Value) -> Result<Vec<char>, LogoError> {
+    match thing {
+        Value::List(_) => Err(thing.type_error(Kind::Word)),
+        _ => Ok(thing.to_string().chars().collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(w: &str) -> Value {
+        Value::Word(w.into())
+    }
+
+    fn list(items: &[&str]) -> Value {
+        Value::List(items.iter().map(|item| word(item)).collect())
+    }
+
+    fn shown(p: Primitive, inputs: Vec<Value>) -> String {
+        apply(p, &inputs).expect("inputs fit").to_string()
+    }
+
+    #[test]
+    fn pieces_of_lists_and_words() {
+        let abc = list(&["a", "b", "c"]);
+        assert_eq!(shown(Primitive::First, vec![abc.clone()]), "a");
+        assert_eq!(shown(Primitive::Last, vec![abc.clone()]), "c");
+        assert_eq!(shown(Primitive::Butfirst, vec![abc.clone()]), "[b c]");
+        assert_eq!(shown(Primitive::Item, vec![Value::Number(2.0), abc]), "b");
+        assert_eq!(shown(Primitive::First, vec![word("logo")]), "l");
+        assert_eq!(shown(Primitive::Last, vec![Value::Number(42.0)]), "2");
+        assert_eq!(shown(Primitive::Butfirst, vec![word("logo")]), "ogo");
+        assert_eq!(
+            shown(Primitive::Item, vec![Value::Number(3.0), word("logo")]),
+            "g"
+        );
+    }
+
+    #[test]
+    fn building_lists_and_words() {
+        let ab = list(&["a", "b"]);
+        assert_eq!(
+            shown(Primitive::Fput, vec![word("x"), ab.clone()]),
+            "[x a b]"
+        );
+        assert_eq!(
+            shown(Primitive::Lput, vec![word("x"), ab.clone()]),
+            "[a b x]"
+        );
+        assert_eq!(shown(Primitive::Fput, vec![word("x"), word("ab")]), "xab");
+        assert_eq!(shown(Primitive::Lput, vec![word("x"), word("ab")]), "abx");
+        assert_eq!(
+            shown(Primitive::Word, vec![word("ab"), Value::Number(1.0)]),
+            "ab1"
+        );
+        assert_eq!(
+            shown(Primitive::Sentence, vec![ab.clone(), word("c")]),
+            "[a b c]"
+        );
+        assert_eq!(shown(Primitive::List, vec![ab, word("c")]), "[[a b] c]");
+    }
+
+    #[test]
+    fn counting_and_membership() {
+        let ab = list(&["a", "b"]);
+        assert_eq!(shown(Primitive::Count, vec![ab.clone()]), "2");
+        assert_eq!(shown(Primitive::Count, vec![word("logo")]), "4");
+        assert_eq!(shown(Primitive::Emptyp, vec![list(&[])]), "true");
+        assert_eq!(shown(Primitive::Emptyp, vec![word("")]), "true");
+        assert_eq!(shown(Primitive::Memberp, vec![word("B"), ab]), "true");
+        assert_eq!(
+            shown(Primitive::Memberp, vec![word("o"), word("logo")]),
+            "true"
+        );
+        assert_eq!(
+            shown(Primitive::Memberp, vec![word("lo"), word("logo")]),
+            "false"
+        );
+    }
+
+    #[test]
+    fn what_does_not_fit_is_a_bad_input() {
+        for (p, inputs) in [
+            (Primitive::First, vec![list(&[])]),
+            (Primitive::Butfirst, vec![word("")]),
+            (Primitive::Item, vec![Value::Number(0.0), list(&["a"])]),
+            (Primitive::Item, vec![Value::Number(1.5), list(&["a"])]),
+            (Primitive::Item, vec![Value::Number(2.0), word("a")]),
+        ] {
+            assert!(
+                matches!(apply(p, &inputs), Err(LogoError::BadInput(..))),
+                "{p:?} {inputs:?}"
+            );
+        }
+        assert!(matches!(
+            apply(Primitive::Word, &[list(&[]), word("a")]),
+            Err(LogoError::TypeError { .. })
+        ));
+        assert!(matches!(
+            apply(Primitive::Count, &[]),
+            Err(LogoError::ArityMismatch {
+                expected: 1,
+                got: 0,
+                ..
+            })
+        ));
+    }
+}
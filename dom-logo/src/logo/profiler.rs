@@ -0,0 +1,138 @@
+use super::compiler::Program;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Work done in one place: procedure calls made, instructions run and
+/// commands emitted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    pub calls: usize,
+    pub steps: usize,
+    pub commands: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: Self) {
+        self.calls += other.calls;
+        self.steps += other.steps;
+        self.commands += other.commands;
+    }
+}
+
+/// Work done in a procedure's own body. `calls` is how many times it was
+/// called.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcedureProfile {
+    pub name: String,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+/// Work done by the statements on a source line. `calls` is how many
+/// procedure calls they made.
+#[derive(Debug, Clone, Serialize)]
+pub struct LineProfile {
+    pub line: usize,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+/// Where a program spent its budget
+#[derive(Debug, Clone, Serialize)]
+pub struct Profile {
+    pub total: Counts,
+    /// Busiest first
+    pub procedures: Vec<ProcedureProfile>,
+    /// In source order
+    pub lines: Vec<LineProfile>,
+}
+
+/// Counts by instruction, summed up by procedure and line when reported
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Profiler {
+    /// The instruction being run, which the work is counted toward
+    site: u32,
+    steps: Vec<usize>,
+    commands: Vec<usize>,
+    calls: Vec<usize>,
+    /// Calls of each compiled procedure
+    procs: Vec<usize>,
+}
+
+impl Profiler {
+    pub fn step(&mut self, pc: u32) {
+        self.site = pc;
+        *Self::slot(&mut self.steps, pc as usize) += 1;
+    }
+
+    pub fn emitted(&mut self, commands: usize) {
+        if commands > 0 {
+            *Self::slot(&mut self.commands, self.site as usize) += commands;
+        }
+    }
+
+    pub fn called(&mut self, proc: u32) {
+        *Self::slot(&mut self.calls, self.site as usize) += 1;
+        *Self::slot(&mut self.procs, proc as usize) += 1;
+    }
+
+    pub fn report(&self, program: &Program) -> Profile {
+        let mut total = Counts::default();
+        let mut procedures: BTreeMap<&str, Counts> = BTreeMap::new();
+        let mut lines: BTreeMap<usize, Counts> = BTreeMap::new();
+        let len = self
+            .steps
+            .len()
+            .max(self.commands.len())
+            .max(self.calls.len());
+        for pc in 0..len {
+            let counts = Counts {
+                calls: self.calls.get(pc).copied().unwrap_or(0),
+                steps: self.steps.get(pc).copied().unwrap_or(0),
+                commands: self.commands.get(pc).copied().unwrap_or(0),
+            };
+            if counts == Counts::default() {
+                continue;
+            }
+            total.add(counts);
+            let pc = pc as u32;
+            if let Some(span) = program.span_at(pc) {
+                lines.entry(span.line).or_default().add(counts);
+            }
+            if let Some(proc) = program.proc_at(pc) {
+                // calls made from the body count toward the callee instead
+                procedures
+                    .entry(program.name(proc.name))
+                    .or_default()
+                    .add(Counts { calls: 0, ..counts });
+            }
+        }
+        for (proc, calls) in self.procs.iter().enumerate().filter(|(_, c)| **c > 0) {
+            let name = program.name(program.procs[proc].name);
+            procedures.entry(name).or_default().calls += calls;
+        }
+        let mut procedures: Vec<_> = procedures
+            .into_iter()
+            .map(|(name, counts)| ProcedureProfile {
+                name: name.to_string(),
+                counts,
+            })
+            .collect();
+        procedures.sort_by_key(|p| std::cmp::Reverse(p.counts.steps));
+        Profile {
+            total,
+            procedures,
+            lines: lines
+                .into_iter()
+                .map(|(line, counts)| LineProfile { line, counts })
+                .collect(),
+        }
+    }
+
+    fn slot(counts: &mut Vec<usize>, index: usize) -> &mut usize {
+        if counts.len() <= index {
+            counts.resize(index + 1, 0);
+        }
+        &mut counts[index]
+    }
+}
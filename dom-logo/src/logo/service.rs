@@ -0,0 +1,488 @@
+// Answers the editor's questions about a program while it is being written:
+// what can be typed at the cursor, what a word means, where a procedure or
+// input is defined and where it is used.
+//
+// It works on tokens rather than on the AST, so it keeps answering while the
+// code doesn't parse yet, as with a `TO` still missing its `END`, skipping
+// what doesn't even lex.
+//
+use super::locale::Locale;
+use super::parser::{self, same, Keyword, Span, Token, KEYWORDS};
+use super::LogoError;
+use serde::Serialize;
+
+/// What a completion is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Kind {
+    Primitive,
+    /// A short or localized spelling of a primitive
+    Alias,
+    Procedure,
+    /// An input of a procedure the cursor is in
+    Input,
+}
+
+/// Something that can be typed at the cursor
+#[derive(Debug, Clone, Serialize)]
+pub struct Completion {
+    pub label: String,
+    pub kind: Kind,
+    /// Its signature
+    pub detail: String,
+}
+
+/// What a word means, for the editor to show when hovering over it
+#[derive(Debug, Clone, Serialize)]
+pub struct Hover {
+    /// The word
+    pub span: Span,
+    pub signature: String,
+    pub description: String,
+}
+
+/// A `TO` and the code up to its `END`
+struct Procedure {
+    name: String,
+    /// Where the name is in its `TO`
+    span: Span,
+    inputs: Vec<(String, Span)>,
+    /// Byte range from `TO` to `END`, or to the end of the code if the `END`
+    /// isn't written yet
+    start: usize,
+    end: usize,
+}
+
+impl Procedure {
+    fn signature(&self, locale: &Locale) -> String {
+        let mut words = vec![spell(locale, "to"), self.name.clone()];
+        words.extend(self.inputs.iter().map(|(input, _)| input.clone()));
+        words.join(" ")
+    }
+
+    fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+
+    fn input(&self, name: &str) -> Option<&Span> {
+        self.inputs
+            .iter()
+            .find(|(input, _)| same(input, name))
+            .map(|(_, span)| span)
+    }
+}
+
+/// The tokens of a program, and the procedures they define
+struct Outline {
+    tokens: Vec<(Token, Span)>,
+    procs: Vec<Procedure>,
+}
+
+impl Outline {
+    fn new(code: &str, locale: &Locale) -> Result<Self, LogoError> {
+        let mut text = code.to_string();
+        let (tokens, spans) = loop {
+            match parser::tokenize(&text, locale) {
+                // skip what doesn't lex yet, as a `:` still to be followed
+                // by a name
+                Err(LogoError::UnexpectedInput { span, .. }) => {
+                    text.replace_range(span.start..span.end, &" ".repeat(span.end - span.start));
+                }
+                result => break result?,
+            }
+        };
+        let tokens: Vec<(Token, Span)> = tokens.into_iter().zip(spans).collect();
+        let mut procs = vec![];
+        for (i, (token, span)) in tokens.iter().enumerate() {
+            let (name, name_span) = match (token, tokens.get(i + 1)) {
+                (Token::To, Some((Token::Ident(name), span))) => (name, span),
+                _ => continue,
+            };
+            let inputs = tokens[i + 2..]
+                .iter()
+                .map_while(|(token, span)| match token {
+                    Token::Var(input) => Some((input.clone(), span.clone())),
+                    _ => None,
+                })
+                .collect();
+            let mut depth = 0;
+            let end = tokens[i..]
+                .iter()
+                .find(|(token, _)| {
+                    match token {
+                        Token::To => depth += 1,
+                        Token::End => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map_or(code.len(), |(_, span)| span.end);
+            procs.push(Procedure {
+                name: name.clone(),
+                span: name_span.clone(),
+                inputs,
+                start: span.start,
+                end,
+            });
+        }
+        Ok(Self { tokens, procs })
+    }
+
+    /// The token at `offset`
+    fn at(&self, offset: usize) -> Option<&(Token, Span)> {
+        self.tokens
+            .iter()
+            .find(|(_, span)| span.start <= offset && offset < span.end)
+    }
+
+    /// The procedure named `name`: the last one, as it replaces the others
+    fn procedure(&self, name: &str) -> Option<&Procedure> {
+        self.procs.iter().rev().find(|proc| same(&proc.name, name))
+    }
+
+    /// The innermost procedure around `offset` that has the input `name`
+    fn declaring(&self, name: &str, offset: usize) -> Option<&Procedure> {
+        self.procs
+            .iter()
+            .rev()
+            .find(|proc| proc.contains(offset) && proc.input(name).is_some())
+    }
+}
+
+/// What can be typed at `line` and `column`, both 1-based: primitives, their
+/// aliases, the program's procedures and, after a `:`, the inputs of the
+/// procedures around the cursor
+pub fn completions(
+    code: &str,
+    locale: &Locale,
+    line: usize,
+    column: usize,
+) -> Result<Vec<Completion>, LogoError> {
+    let offset = offset(code, line, column);
+    let prefix = &code[code[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == ':')
+        .last()
+        .map_or(offset, |(i, _)| i)..offset];
+    let outline = Outline::new(code, locale)?;
+    let mut completions = vec![];
+    if prefix.starts_with(':') {
+        for proc in outline.procs.iter().filter(|proc| proc.contains(offset)) {
+            for (input, _) in &proc.inputs {
+                completions.push(Completion {
+                    label: input.clone(),
+                    kind: Kind::Input,
+                    detail: proc.signature(locale),
+                });
+            }
+        }
+    } else {
+        for keyword in KEYWORDS {
+            completions.push(Completion {
+                label: keyword.name.to_uppercase(),
+                kind: Kind::Primitive,
+                detail: signature(locale, keyword),
+            });
+            let aliases = keyword
+                .aliases
+                .iter()
+                .copied()
+                .chain(locale.spellings(keyword.name));
+            for alias in aliases {
+                completions.push(Completion {
+                    label: alias.to_uppercase(),
+                    kind: Kind::Alias,
+                    detail: signature(locale, keyword),
+                });
+            }
+        }
+        for (i, proc) in outline.procs.iter().enumerate() {
+            // a procedure defined again is offered once, as it ends up
+            if outline.procs[i + 1..]
+                .iter()
+                .any(|later| same(&later.name, &proc.name))
+            {
+                continue;
+            }
+            completions.push(Completion {
+                label: proc.name.clone(),
+                kind: Kind::Procedure,
+                detail: proc.signature(locale),
+            });
+        }
+    }
+    let prefix = prefix.to_lowercase();
+    completions.retain(|completion| completion.label.to_lowercase().starts_with(&prefix));
+    Ok(completions)
+}
+
+/// What the word at `line` and `column` means: a primitive's signature and
+/// what it does, or where a procedure or input comes from
+pub fn hover(
+    code: &str,
+    locale: &Locale,
+    line: usize,
+    column: usize,
+) -> Result<Option<Hover>, LogoError> {
+    let offset = offset(code, line, column);
+    let outline = Outline::new(code, locale)?;
+    let (token, span) = match outline.at(offset) {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let hover = match token {
+        Token::Ident(name) | Token::Word(name) => outline.procedure(name).map(|proc| Hover {
+            span: span.clone(),
+            signature: proc.signature(locale),
+            description: format!("procedure defined on line {}", proc.span.line),
+        }),
+        Token::Var(name) => outline.declaring(name, offset).map(|proc| Hover {
+            span: span.clone(),
+            signature: name.clone(),
+            description: format!("input of `{}`", proc.name),
+        }),
+        Token::Number(_) | Token::LBracket | Token::RBracket => None,
+        token => KEYWORDS
+            .iter()
+            .find(|keyword| keyword.token == *token)
+            .map(|keyword| Hover {
+                span: span.clone(),
+                signature: signature(locale, keyword),
+                description: keyword.description.to_string(),
+            }),
+    };
+    Ok(hover)
+}
+
+/// Where the procedure or input at `line` and `column` is defined
+pub fn definition(
+    code: &str,
+    locale: &Locale,
+    line: usize,
+    column: usize,
+) -> Result<Option<Span>, LogoError> {
+    let offset = offset(code, line, column);
+    let outline = Outline::new(code, locale)?;
+    Ok(match outline.at(offset) {
+        Some((Token::Ident(name) | Token::Word(name), _)) => {
+            outline.procedure(name).map(|proc| proc.span.clone())
+        }
+        Some((Token::Var(name), _)) => outline
+            .declaring(name, offset)
+            .and_then(|proc| proc.input(name))
+            .cloned(),
+        _ => None,
+    })
+}
+
+/// Everywhere the procedure or input at `line` and `column` is defined and
+/// used, including words naming the procedure, as for APPLY
+pub fn references(
+    code: &str,
+    locale: &Locale,
+    line: usize,
+    column: usize,
+) -> Result<Vec<Span>, LogoError> {
+    let offset = offset(code, line, column);
+    let outline = Outline::new(code, locale)?;
+    let references = match outline.at(offset) {
+        Some((Token::Ident(name) | Token::Word(name), _)) => outline
+            .tokens
+            .iter()
+            .filter_map(|(token, span)| match token {
+                Token::Ident(other) if same(other, name) => Some(span.clone()),
+                // the name, without the quote
+                Token::Word(other) if same(other, name) => Some(Span {
+                    column: span.column + 1,
+                    start: span.start + 1,
+                    ..span.clone()
+                }),
+                _ => None,
+            })
+            .collect(),
+        Some((Token::Var(name), _)) => {
+            let proc = outline.declaring(name, offset);
+            outline
+                .tokens
+                .iter()
+                .filter(|(token, span)| {
+                    matches!(token, Token::Var(other) if same(other, name))
+                        && match proc {
+                            Some(proc) => proc.contains(span.start),
+                            None => outline.declaring(name, span.start).is_none(),
+                        }
+                })
+                .map(|(_, span)| span.clone())
+                .collect()
+        }
+        _ => vec![],
+    };
+    Ok(references)
+}
+
+/// A keyword with its inputs, named as `locale` spells it
+fn signature(locale: &Locale, keyword: &Keyword) -> String {
+    if keyword.inputs.is_empty() {
+        spell(locale, keyword.name)
+    } else {
+        format!("{} {}", spell(locale, keyword.name), keyword.inputs)
+    }
+}
+
+/// A keyword in `locale`'s language if it has a spelling for it, in
+/// uppercase
+fn spell(locale: &Locale, name: &str) -> String {
+    locale.spellings(name).next().unwrap_or(name).to_uppercase()
+}
+
+/// The byte offset of a 1-based line and column, counting characters
+fn offset(code: &str, line: usize, column: usize) -> usize {
+    let start = if line <= 1 {
+        0
+    } else {
+        match code.match_indices('\n').nth(line - 2) {
+            Some((i, _)) => i + 1,
+            None => return code.len(),
+        }
+    };
+    let text = &code[start..];
+    let text = &text[..text.find('\n').unwrap_or(text.len())];
+    start
+        + text
+            .char_indices()
+            .nth(column.saturating_sub(1))
+            .map_or(text.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logo::locale::{ENGLISH, HEBREW};
+
+    const SQUARE: &str = "to square :side\nrepeat 4 [fd :side rt 90]\nend\nsquare 10\n";
+
+    fn labels(code: &str, locale: &Locale, line: usize, column: usize) -> Vec<(String, Kind)> {
+        completions(code, locale, line, column)
+            .expect("code must tokenize")
+            .into_iter()
+            .map(|completion| (completion.label, completion.kind))
+            .collect()
+    }
+
+    fn text(code: &str, span: &Span) -> String {
+        code[span.start..span.end].to_string()
+    }
+
+    #[test]
+    fn every_keyword_reads_as_its_token() {
+        for keyword in KEYWORDS {
+            for spelling in std::iter::once(keyword.name).chain(keyword.aliases.iter().copied()) {
+                let (tokens, _) = parser::tokenize(spelling, &ENGLISH).expect("keywords lex");
+                assert_eq!(tokens.front(), Some(&keyword.token), "{spelling}");
+            }
+        }
+    }
+
+    #[test]
+    fn completions_offer_keywords_their_aliases_and_procedures() {
+        let found = labels(SQUARE, &ENGLISH, 4, 2);
+        assert!(found.contains(&("square".into(), Kind::Procedure)));
+        assert!(found.contains(&("SETSHAPE".into(), Kind::Primitive)));
+        assert!(found.contains(&("ST".into(), Kind::Alias)));
+        assert!(found
+            .iter()
+            .all(|(label, _)| label.to_lowercase().starts_with('s')));
+        let found = labels("f", &ENGLISH, 1, 2);
+        assert!(found.contains(&("FORWARD".into(), Kind::Primitive)));
+        assert!(found.contains(&("FD".into(), Kind::Alias)));
+    }
+
+    #[test]
+    fn completions_offer_local_spellings() {
+        let found = labels("ק", &HEBREW, 1, 2);
+        assert!(found.contains(&("קד".into(), Kind::Alias)));
+        let detail = completions("fd", &HEBREW, 1, 3).expect("code must tokenize");
+        assert!(detail
+            .iter()
+            .any(|completion| completion.label == "FD" && completion.detail == "קד :distance"));
+    }
+
+    #[test]
+    fn completions_offer_inputs_only_inside_their_procedure() {
+        let found = labels(SQUARE, &ENGLISH, 2, 16);
+        assert_eq!(found, [(":side".into(), Kind::Input)]);
+        assert!(labels("to a :x\nend\n:", &ENGLISH, 3, 2).is_empty());
+    }
+
+    #[test]
+    fn hover_describes_keywords_from_the_table() {
+        let hover = hover(SQUARE, &ENGLISH, 2, 12).expect("code must tokenize");
+        let hover = hover.expect("FD has docs");
+        assert_eq!(hover.signature, "FORWARD :distance");
+        assert_eq!(
+            hover.description,
+            Keyword::named("forward").unwrap().description
+        );
+        assert_eq!(text(SQUARE, &hover.span), "fd");
+        let hover = super::hover("print first [a b]", &ENGLISH, 1, 8).expect("code must tokenize");
+        assert_eq!(hover.expect("FIRST has docs").signature, "FIRST :thing");
+    }
+
+    #[test]
+    fn hover_points_procedures_and_inputs_at_their_definition() {
+        let over = hover(SQUARE, &ENGLISH, 4, 2).unwrap().unwrap();
+        assert_eq!(over.signature, "TO square :side");
+        assert_eq!(over.description, "procedure defined on line 1");
+        let over = hover(SQUARE, &ENGLISH, 2, 15).unwrap().unwrap();
+        assert_eq!(over.description, "input of `square`");
+        assert!(hover(SQUARE, &ENGLISH, 4, 9).unwrap().is_none());
+    }
+
+    #[test]
+    fn definition_of_procedures_and_inputs() {
+        let span = definition(SQUARE, &ENGLISH, 4, 1).unwrap().unwrap();
+        assert_eq!((span.line, text(SQUARE, &span)), (1, "square".into()));
+        let span = definition(SQUARE, &ENGLISH, 2, 15).unwrap().unwrap();
+        assert_eq!((span.line, text(SQUARE, &span)), (1, ":side".into()));
+        assert!(definition(SQUARE, &ENGLISH, 2, 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn references_include_words_naming_the_procedure() {
+        let code = "to sq\nfd 10\nend\nSQ\napply \"sq []\n";
+        let spans = references(code, &ENGLISH, 4, 1).unwrap();
+        let found: Vec<(usize, String)> = spans
+            .iter()
+            .map(|span| (span.line, text(code, span)))
+            .collect();
+        assert_eq!(
+            found,
+            [(1, "sq".into()), (4, "SQ".into()), (5, "sq".into())]
+        );
+    }
+
+    #[test]
+    fn references_to_inputs_stay_in_their_procedure() {
+        let code = "to a :x\nfd :x\nend\nto b :x\nrt :x\nend\n";
+        let lines: Vec<usize> = references(code, &ENGLISH, 2, 5)
+            .unwrap()
+            .iter()
+            .map(|span| span.line)
+            .collect();
+        assert_eq!(lines, [1, 2]);
+    }
+
+    #[test]
+    fn code_that_does_not_lex_yet_is_still_answered() {
+        let code = "to sq :\nfd 10\nend\nsq";
+        let span = definition(code, &ENGLISH, 4, 1).unwrap().unwrap();
+        assert_eq!(span.line, 1);
+    }
+
+    #[test]
+    fn offsets_count_characters() {
+        assert_eq!(offset("קד 10\nfd", 1, 3), "קד".len());
+        assert_eq!(offset("a\nfd", 2, 2), 3);
+        assert_eq!(offset("a", 5, 1), 1);
+    }
+}
@@ -1,8 +1,12 @@
 #![allow(clippy::cast_possible_truncation)]
 #![allow(clippy::cast_precision_loss)]
+#![allow(clippy::cast_sign_loss)]
 
 use serde::Serialize;
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
 
+use super::parser::Span;
 use super::LogoError;
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize)]
@@ -15,49 +19,206 @@ pub enum Command {
     Pensize(f32),
     Line((f32, f32), (f32, f32)),
     Move((f32, f32), (f32, f32)),
+    Visible(bool),
+    Shape(Shape),
+    Stamp {
+        shape: Shape,
+        position: (f32, f32),
+        heading: f32,
+        color: Option<(u8, u8, u8)>,
+    },
+    Speed(f32),
+    Wait(f32),
+    Label(String),
 }
 
-#[derive(Clone)]
-pub struct Turtle {
-    pub commands: Vec<Command>,
+impl Command {
+    /// How many ticks (60ths of a second) this command takes to play out at
+    /// `speed` units per tick. Lines and arcs take one tick at most when the
+    /// speed isn't positive, and commands that don't draw are instant.
+    pub fn ticks(&self, speed: f32) -> usize {
+        let length = match self {
+            Self::Wait(ticks) => return ticks.max(0.0).round() as usize,
+            Self::Line((sx, sy), (x, y)) => (x - sx).hypot(y - sy),
+            Self::Arc(angle, rad) => angle.to_radians().abs() * rad.abs(),
+            Self::Circle(rad) => 2.0 * PI * rad.abs(),
+            _ => return 0,
+        };
+        if speed <= 0.0 {
+            return 1;
+        }
+        ((length / speed).ceil() as usize).max(1)
+    }
+}
+
+/// Turtle head shapes. Polygon points are in turtle-local coordinates:
+/// `x` points to the turtle's right and `y` points forward, at a zoom of 1.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Shape {
+    Triangle,
+    Turtle,
+    Arrow,
+    Circle,
+    Polygon(Vec<(f32, f32)>),
+}
+
+/// A [`Command`] tagged with the id of the turtle that issued it, and the
+/// tick it starts at when played at the speeds and waits the program asked for.
+/// Each turtle keeps its own time, so records of different turtles overlap.
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub turtle: usize,
+    pub time: usize,
+    pub command: Command,
+}
+
+/// Where a [`Record`] came from: the statement that emitted it, and the
+/// procedure calls it was made in, outermost first
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Origin {
+    pub span: Option<Span>,
+    pub calls: Vec<Caller>,
+}
+
+/// A procedure call on the way to a command, and the statement that made it
+#[derive(Debug, Clone, Serialize)]
+pub struct Caller {
+    pub procedure: String,
+    pub span: Option<Span>,
+}
+
+/// Units a line advances per tick, unless a program says otherwise
+pub const DEFAULT_SPEED: f32 = 10.0;
+
+/// Upper bound on turtle ids, so a typo can't allocate the world
+pub const MAX_TURTLES: usize = 1024;
+
+/// State each turtle carries on its own
+#[derive(Debug, Clone, Serialize)]
+pub struct TurtleState {
     pub position: (f32, f32),
     pub angle: f32,
     pub pendown: bool,
-    pub budget: usize,
+    pub color: Option<(u8, u8, u8)>,
+    pub shape: Shape,
+    /// The tick this turtle's next command starts at, so turtles draw at
+    /// the same time rather than in turn
+    pub clock: usize,
+    /// Units this turtle's lines advance per tick
+    pub speed: f32,
 }
 
-impl Turtle {
-    pub fn new(position: (f32, f32), budget: usize) -> Self {
+impl TurtleState {
+    /// Where `n` steps forward take the turtle
+    fn ahead(&self, n: f32) -> (f32, f32) {
+        let rads = self.angle.to_radians();
+        (
+            rads.sin().mul_add(n, self.position.0),
+            rads.cos().mul_add(-n, self.position.1),
+        )
+    }
+
+    fn new(position: (f32, f32), clock: usize, speed: f32) -> Self {
         Self {
             position,
-            commands: vec![Command::Move((0.0, 0.0), position)],
             angle: 0.0,
             pendown: true,
+            color: None,
+            shape: Shape::Triangle,
+            clock,
+            speed,
+        }
+    }
+}
+
+/// The turtles, the ones currently listening to commands, and everything
+/// they drew so far.
+#[derive(Clone)]
+pub struct Turtle {
+    pub commands: Vec<Record>,
+    pub turtles: BTreeMap<usize, TurtleState>,
+    pub active: Vec<usize>,
+    pub home: (f32, f32),
+    /// The speed turtles hatch with
+    speed: f32,
+    pub budget: usize,
+    /// Where each command came from, alongside `commands`, when tracing
+    pub trace: Option<Vec<Origin>>,
+    /// Moves off the canvas fail, as after `FENCE`
+    pub fenced: bool,
+    /// Width and height of the canvas `FENCE` keeps turtles on, which
+    /// starts at the origin. Without one, no move is off the canvas.
+    pub canvas: Option<(f32, f32)>,
+}
+
+impl Turtle {
+    pub fn new(position: (f32, f32), budget: usize) -> Self {
+        Self {
+            commands: vec![Record {
+                turtle: 0,
+                time: 0,
+                command: Command::Move((0.0, 0.0), position),
+            }],
+            turtles: BTreeMap::from([(0, TurtleState::new(position, 0, DEFAULT_SPEED))]),
+            active: vec![0],
+            home: position,
+            speed: DEFAULT_SPEED,
             budget,
+            trace: None,
+            fenced: false,
+            canvas: None,
         }
     }
 
-    pub fn clear(&mut self) {
-        self.commands.push(Command::Clear);
+    /// Sets the speed turtles hatch with, and that of the ones hatched
+    /// already, before the program sets its own
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        for state in self.turtles.values_mut() {
+            state.speed = speed;
+        }
     }
 
-    pub fn setcolor(&mut self, c: (u8, u8, u8)) {
-        self.commands.push(Command::Color(c));
+    /// Starts recording where each command comes from. Commands so far have
+    /// no known origin.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(vec![Origin::default(); self.commands.len()]);
     }
 
-    pub fn forward(&mut self, n: f32) -> Result<(), LogoError> {
+    /// Records `origin` for the commands emitted since the last trace
+    pub(crate) fn traced(&mut self, origin: &Origin) {
+        if let Some(trace) = &mut self.trace {
+            trace.resize(self.commands.len(), origin.clone());
+        }
+    }
+
+    pub fn clear(&mut self) -> Result<(), LogoError> {
         self.guard_budget()?;
+        self.push(self.who(), Command::Clear);
+        Ok(())
+    }
 
-        let rads = self.angle.to_radians();
-        let x = rads.sin().mul_add(n, self.position.0);
-        let y = rads.cos().mul_add(-n, self.position.1);
-        if self.pendown {
-            self.commands.push(Command::Line(self.position, (x, y)));
-        } else {
-            self.commands.push(Command::Move(self.position, (x, y)));
+    pub fn setcolor(&mut self, c: (u8, u8, u8)) -> Result<(), LogoError> {
+        self.each(|t| {
+            t.color = Some(c);
+            Command::Color(c)
+        })
+    }
+
+    pub fn forward(&mut self, n: f32) -> Result<(), LogoError> {
+        for id in &self.active {
+            self.guard_bounds(self.turtles[id].ahead(n))?;
         }
-        self.position = (x, y);
-        Ok(())
+        self.each(|t| {
+            let (x, y) = t.ahead(n);
+            let from = t.position;
+            t.position = (x, y);
+            if t.pendown {
+                Command::Line(from, (x, y))
+            } else {
+                Command::Move(from, (x, y))
+            }
+        })
     }
 
     pub fn back(&mut self, n: f32) -> Result<(), LogoError> {
@@ -65,42 +226,155 @@ impl Turtle {
     }
 
     pub fn right(&mut self, n: f32) -> Result<(), LogoError> {
-        self.guard_budget()?;
-
-        self.angle = (((self.angle + n).floor() as i32) % 360) as f32;
-        self.commands.push(Command::Heading(self.angle));
-        Ok(())
+        self.each(|t| {
+            t.angle = (((t.angle + n).floor() as i32) % 360) as f32;
+            Command::Heading(t.angle)
+        })
     }
 
     pub fn left(&mut self, n: f32) -> Result<(), LogoError> {
         self.right(-n)
     }
 
-    pub fn commands(&self) -> Vec<Command> {
+    pub fn pendown(&mut self, down: bool) {
+        for id in &self.active {
+            if let Some(t) = self.turtles.get_mut(id) {
+                t.pendown = down;
+            }
+        }
+    }
+
+    pub fn commands(&self) -> Vec<Record> {
         self.commands.clone()
     }
 
+    /// The first turtle listening to commands
+    pub fn who(&self) -> usize {
+        self.active[0]
+    }
+
+    /// Directs the following commands at `ids`, hatching turtles at home as
+    /// needed, from the time the turtle telling them is at and at the speed
+    /// turtles hatch with. Returns the previously active turtles.
+    pub(crate) fn tell(&mut self, ids: Vec<usize>) -> Result<Vec<usize>, LogoError> {
+        if ids.is_empty() {
+            return Err(LogoError::InvalidTurtle("[]".into()));
+        }
+        for id in &ids {
+            if *id >= MAX_TURTLES {
+                return Err(LogoError::InvalidTurtle(id.to_string()));
+            }
+            if !self.turtles.contains_key(id) {
+                self.guard_budget()?;
+                let now = self.turtles[&self.who()].clock;
+                let state = TurtleState::new(self.home, now, self.speed);
+                self.turtles.insert(*id, state);
+                self.push(*id, Command::Move((0.0, 0.0), self.home));
+            }
+        }
+        Ok(std::mem::replace(&mut self.active, ids))
+    }
+
     pub(crate) fn position(&mut self, x: f32, y: f32) -> Result<(), LogoError> {
-        self.guard_budget()?;
-        self.commands.push(Command::Move(self.position, (x, y)));
-        Ok(())
+        self.guard_bounds((x, y))?;
+        self.each(|t| {
+            let from = t.position;
+            t.position = (x, y);
+            Command::Move(from, (x, y))
+        })
     }
 
     pub(crate) fn arc(&mut self, angle: f32, radius: f32) -> Result<(), LogoError> {
-        self.guard_budget()?;
-        self.commands.push(Command::Arc(angle, radius));
-        Ok(())
+        self.each(|_| Command::Arc(angle, radius))
     }
 
     pub(crate) fn circle(&mut self, rad: f32) -> Result<(), LogoError> {
-        self.guard_budget()?;
-        self.commands.push(Command::Circle(rad));
-        Ok(())
+        self.each(|_| Command::Circle(rad))
+    }
+
+    pub(crate) fn showturtle(&mut self, visible: bool) -> Result<(), LogoError> {
+        self.each(|_| Command::Visible(visible))
+    }
+
+    pub(crate) fn setshape(&mut self, shape: &Shape) -> Result<(), LogoError> {
+        self.each(|t| {
+            t.shape = shape.clone();
+            Command::Shape(shape.clone())
+        })
+    }
+
+    pub(crate) fn stamp(&mut self) -> Result<(), LogoError> {
+        self.each(|t| Command::Stamp {
+            shape: t.shape.clone(),
+            position: t.position,
+            heading: t.angle,
+            color: t.color,
+        })
+    }
+
+    pub(crate) fn setspeed(&mut self, speed: f32) -> Result<(), LogoError> {
+        self.each(|t| {
+            t.speed = speed;
+            Command::Speed(speed)
+        })
+    }
+
+    pub(crate) fn wait(&mut self, ticks: f32) -> Result<(), LogoError> {
+        self.each(|_| Command::Wait(ticks))
+    }
+
+    pub(crate) fn label(&mut self, text: String) -> Result<(), LogoError> {
+        self.each(|_| Command::Label(text.clone()))
+    }
+
+    /// Keeps turtles on the canvas from now on, or lets them off it again.
+    /// A move that would take a turtle off it fails before any turtle moves.
+    pub(crate) fn fence(&mut self, fenced: bool) {
+        self.fenced = fenced;
     }
 
     pub(crate) fn pensize(&mut self, s: f32) -> Result<(), LogoError> {
-        self.guard_budget()?;
-        self.commands.push(Command::Pensize(s));
+        self.each(|_| Command::Pensize(s))
+    }
+
+    /// Applies `f` to every active turtle, recording the command it returns
+    fn each(&mut self, mut f: impl FnMut(&mut TurtleState) -> Command) -> Result<(), LogoError> {
+        for i in 0..self.active.len() {
+            self.guard_budget()?;
+            let id = self.active[i];
+            let state = self
+                .turtles
+                .get_mut(&id)
+                .expect("active turtles are always hatched");
+            let command = f(state);
+            self.push(id, command);
+        }
+        Ok(())
+    }
+
+    /// Records `command` for `turtle`, at the time on its own clock
+    fn push(&mut self, turtle: usize, command: Command) {
+        let state = self
+            .turtles
+            .get_mut(&turtle)
+            .expect("turtles are hatched before they draw");
+        let time = state.clock;
+        state.clock += command.ticks(state.speed);
+        self.commands.push(Record {
+            turtle,
+            time,
+            command,
+        });
+    }
+
+    /// Fails a move to `to` off the canvas when fenced
+    fn guard_bounds(&self, to: (f32, f32)) -> Result<(), LogoError> {
+        let Some((width, height)) = self.canvas else {
+            return Ok(());
+        };
+        if self.fenced && !((0.0..=width).contains(&to.0) && (0.0..=height).contains(&to.1)) {
+            return Err(LogoError::OutOfBounds);
+        }
         Ok(())
     }
 
@@ -111,3 +385,75 @@ impl Turtle {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_and_arcs_take_a_tick_per_speed_of_length() {
+        let line = Command::Line((0.0, 0.0), (0.0, 25.0));
+        assert_eq!(line.ticks(10.0), 3);
+        assert_eq!(line.ticks(25.0), 1);
+        assert_eq!(line.ticks(100.0), 1);
+        assert_eq!(Command::Arc(90.0, 10.0).ticks(10.0), 2);
+        assert_eq!(Command::Circle(10.0).ticks(10.0), 7);
+    }
+
+    #[test]
+    fn lines_take_a_tick_when_the_speed_is_not_positive() {
+        let line = Command::Line((0.0, 0.0), (0.0, 25.0));
+        assert_eq!(line.ticks(0.0), 1);
+        assert_eq!(line.ticks(-5.0), 1);
+    }
+
+    #[test]
+    fn commands_that_do_not_draw_are_instant() {
+        assert_eq!(Command::Heading(90.0).ticks(10.0), 0);
+        assert_eq!(Command::Move((0.0, 0.0), (0.0, 100.0)).ticks(10.0), 0);
+        assert_eq!(Command::Speed(1.0).ticks(10.0), 0);
+    }
+
+    #[test]
+    fn records_start_when_the_ones_before_are_played() {
+        let mut turtle = Turtle::new((0.0, 0.0), 100);
+        turtle.forward(25.0).unwrap();
+        turtle.setspeed(5.0).unwrap();
+        turtle.forward(10.0).unwrap();
+        turtle.right(90.0).unwrap();
+        let times: Vec<usize> = turtle.commands.iter().map(|record| record.time).collect();
+        assert_eq!(times, [0, 0, 3, 3, 5]);
+        assert_eq!(turtle.turtles[&0].clock, 5);
+    }
+
+    #[test]
+    fn turtles_keep_time_on_their_own() {
+        let mut turtle = Turtle::new((0.0, 0.0), 100);
+        turtle.forward(30.0).unwrap();
+        turtle.tell(vec![1, 2]).unwrap();
+        turtle.forward(20.0).unwrap();
+        turtle.tell(vec![0]).unwrap();
+        turtle.forward(10.0).unwrap();
+        let times: Vec<(usize, usize)> = turtle
+            .commands
+            .iter()
+            .filter(|record| matches!(record.command, Command::Line(..)))
+            .map(|record| (record.turtle, record.time))
+            .collect();
+        // turtles 1 and 2 hatch at 3, when 0 is done with its line, and
+        // draw side by side; 0 goes on from 3 as well
+        assert_eq!(times, [(0, 0), (1, 3), (2, 3), (0, 3)]);
+    }
+
+    #[test]
+    fn speed_is_set_for_the_turtles_told() {
+        let mut turtle = Turtle::new((0.0, 0.0), 100);
+        turtle.tell(vec![1, 2]).unwrap();
+        turtle.tell(vec![1]).unwrap();
+        turtle.setspeed(5.0).unwrap();
+        turtle.tell(vec![1, 2]).unwrap();
+        turtle.forward(20.0).unwrap();
+        assert_eq!(turtle.turtles[&1].clock, 4);
+        assert_eq!(turtle.turtles[&2].clock, 2);
+    }
+}
@@ -0,0 +1,130 @@
+use super::{Kind, LogoError};
+use std::fmt;
+
+/// Everything a Logo expression can evaluate to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f32),
+    Word(String),
+    List(Vec<Value>),
+    Bool(bool),
+}
+
+impl Value {
+    /// Numbers, and words that read as numbers (`"5` is as good as `5`)
+    pub fn number(&self) -> Result<f32, LogoError> {
+        match self {
+            Self::Number(n) => Ok(*n),
+            Self::Word(w) => w.parse::<f32>().map_err(|_| self.type_error(Kind::Number)),
+            _ => Err(self.type_error(Kind::Number)),
+        }
+    }
+
+    /// Booleans, and the words `true` and `false`
+    pub fn boolean(&self) -> Result<bool, LogoError> {
+        match self {
+            Self::Bool(b) => Ok(*b),
+            Self::Word(w) if w.eq_ignore_ascii_case("true") => Ok(true),
+            Self::Word(w) if w.eq_ignore_ascii_case("false") => Ok(false),
+            _ => Err(self.type_error(Kind::Boolean)),
+        }
+    }
+
+    /// The items of a list
+    pub fn list(&self) -> Result<&[Self], LogoError> {
+        match self {
+            Self::List(items) => Ok(items),
+            _ => Err(self.type_error(Kind::List)),
+        }
+    }
+
+    /// Logo equality: numbers compare with a tolerance, words ignore case
+    pub fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::List(a), Self::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same(b))
+            }
+            (Self::List(_), _) | (_, Self::List(_)) => false,
+            _ => match (self.number(), other.number()) {
+                (Ok(a), Ok(b)) => (a - b).abs() < 0.0001,
+                _ => self.to_string().eq_ignore_ascii_case(&other.to_string()),
+            },
+        }
+    }
+
+    /// How PRINT and LABEL show a value: a list loses its outer brackets
+    pub fn printed(&self) -> String {
+        match self {
+            Self::List(items) => items
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn type_error(&self, expected: Kind) -> LogoError {
+        LogoError::TypeError {
+            expected,
+            got: self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{n}"),
+            Self::Word(w) => write!(f, "{w}"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(w: &str) -> Value {
+        Value::Word(w.into())
+    }
+
+    #[test]
+    fn words_read_as_numbers_and_booleans() {
+        assert_eq!(word("5").number().unwrap(), 5.0);
+        assert!(word("five").number().is_err());
+        assert!(word("TRUE").boolean().unwrap());
+        assert!(!word("false").boolean().unwrap());
+        assert!(Value::List(vec![]).number().is_err());
+    }
+
+    #[test]
+    fn sameness_ignores_case_and_rounding() {
+        assert!(word("Hello").same(&word("hELLO")));
+        assert!(word("5").same(&Value::Number(5.000_01)));
+        assert!(Value::List(vec![word("a"), Value::Number(1.0)])
+            .same(&Value::List(vec![word("A"), word("1")])));
+        assert!(!Value::List(vec![word("a")]).same(&word("a")));
+        assert!(!Value::List(vec![word("a")]).same(&Value::List(vec![])));
+    }
+
+    #[test]
+    fn lists_print_without_their_outer_brackets() {
+        let list = Value::List(vec![word("a"), Value::List(vec![word("b"), word("c")])]);
+        assert_eq!(list.printed(), "a [b c]");
+        assert_eq!(list.to_string(), "[a [b c]]");
+        assert_eq!(Value::List(vec![]).printed(), "");
+        assert_eq!(Value::Number(2.5).printed(), "2.5");
+    }
+}
@@ -0,0 +1,181 @@
+use dom_logo::{format_code, profile_code, Opts};
+use std::io::Read;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: dom-logo format [--abbreviate] [--locale NAME] [FILE]
+       dom-logo profile [--locale NAME] [FILE]
+
+format prints FILE (or standard input when it is missing or `-`) as canonical
+Logo. profile runs it and prints the procedure calls, VM instructions and
+commands of the whole run, of each procedure and of each source line, as JSON.
+
+  --abbreviate    spell keywords with their short alias, FD rather than FORWARD
+  --locale NAME   read and write keywords in NAME: logo (the default), emoji,
+                  hebrew or spanish";
+
+/// what the command line asked for
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Format(Options),
+    Profile(Options),
+}
+
+/// how to read the program, and for `format`, how to write it
+#[derive(Debug, PartialEq, Eq)]
+struct Options {
+    abbreviate: bool,
+    locale: String,
+    file: Option<String>,
+}
+
+/// read the arguments after the program name, or the reason they don't make
+/// sense
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter();
+    let command = match args.next().map(String::as_str) {
+        Some(command @ ("format" | "profile")) => command,
+        Some(command) => return Err(format!("unknown command `{command}`")),
+        None => return Err(String::from("missing command")),
+    };
+    let mut options = Options {
+        abbreviate: false,
+        locale: String::from("logo"),
+        file: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--abbreviate" if command == "format" => options.abbreviate = true,
+            "--locale" => {
+                options.locale = args
+                    .next()
+                    .ok_or_else(|| String::from("`--locale` needs a name"))?
+                    .clone();
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option `{flag}`")),
+            file if options.file.is_none() => options.file = Some(file.to_string()),
+            _ => return Err(format!("only one file can be {command}d at a time")),
+        }
+    }
+    Ok(if command == "format" {
+        Command::Format(options)
+    } else {
+        Command::Profile(options)
+    })
+}
+
+fn read_code(file: Option<&str>) -> Result<String, String> {
+    match file {
+        None | Some("-") => {
+            let mut code = String::new();
+            std::io::stdin()
+                .read_to_string(&mut code)
+                .map_err(|err| format!("can't read standard input: {err}"))?;
+            Ok(code)
+        }
+        Some(path) => {
+            std::fs::read_to_string(path).map_err(|err| format!("can't read {path}: {err}"))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(
+        args.first().map(String::as_str),
+        Some("-h" | "--help" | "help")
+    ) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let (Command::Format(options) | Command::Profile(options)) = &command;
+    let mut opts = Opts::new();
+    opts.abbreviate = options.abbreviate;
+    opts.locale.clone_from(&options.locale);
+    let result = read_code(options.file.as_deref()).and_then(|code| match command {
+        Command::Format(_) => format_code(&opts, &code),
+        Command::Profile(_) => profile_code(&opts, &code).map(|profile| profile + "\n"),
+    });
+    match result {
+        Ok(printed) => {
+            print!("{printed}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn format_reads_its_options_and_file() {
+        assert_eq!(
+            parse_args(&args(&[
+                "format",
+                "--locale",
+                "hebrew",
+                "--abbreviate",
+                "a.logo"
+            ])),
+            Ok(Command::Format(Options {
+                abbreviate: true,
+                locale: String::from("hebrew"),
+                file: Some(String::from("a.logo")),
+            }))
+        );
+        assert_eq!(
+            parse_args(&args(&["format"])),
+            Ok(Command::Format(Options {
+                abbreviate: false,
+                locale: String::from("logo"),
+                file: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn profile_reads_its_locale_and_file() {
+        assert_eq!(
+            parse_args(&args(&["profile", "--locale", "spanish", "a.logo"])),
+            Ok(Command::Profile(Options {
+                abbreviate: false,
+                locale: String::from("spanish"),
+                file: Some(String::from("a.logo")),
+            }))
+        );
+        assert_eq!(
+            parse_args(&args(&["profile", "-"])),
+            Ok(Command::Profile(Options {
+                abbreviate: false,
+                locale: String::from("logo"),
+                file: Some(String::from("-")),
+            }))
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_refused() {
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["draw"])).is_err());
+        assert!(parse_args(&args(&["format", "--locale"])).is_err());
+        assert!(parse_args(&args(&["format", "--tabs"])).is_err());
+        assert!(parse_args(&args(&["format", "a.logo", "b.logo"])).is_err());
+        assert!(parse_args(&args(&["profile", "--abbreviate"])).is_err());
+        assert!(parse_args(&args(&["profile", "a.logo", "b.logo"])).is_err());
+    }
+}
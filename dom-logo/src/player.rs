@@ -0,0 +1,405 @@
+#![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_precision_loss)]
+#![allow(clippy::cast_sign_loss)]
+
+use crate::canvas_plotter::CanvasPlotter;
+use crate::logo::executor::Executor;
+use crate::logo::turtle::{Command, Record, Turtle};
+use crate::Opts;
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+use web_sys::{CanvasRenderingContext2d, ImageData};
+
+/// What a [`Player`] draws on: a plotter, and a place to keep the drawing
+/// while turtle heads are painted over it
+trait Surface {
+    /// clear the drawing and forget what was kept
+    fn reset(&mut self);
+    fn apply(&mut self, record: &Record);
+    fn apply_partial(&mut self, record: &Record, from: f32, to: f32);
+    /// keep the drawing as it is, then paint the heads over it
+    fn overlay(&mut self);
+    /// put back the drawing kept by `overlay`, without the heads
+    fn restore(&mut self);
+}
+
+/// A canvas, keeping its drawing as `ImageData`
+struct CanvasSurface {
+    plotter: CanvasPlotter,
+    context: CanvasRenderingContext2d,
+    size: (u32, u32),
+    // the drawing as it was before heads were painted over it
+    snapshot: Option<ImageData>,
+}
+
+impl Surface for CanvasSurface {
+    fn reset(&mut self) {
+        self.snapshot = None;
+        self.plotter.reset();
+    }
+
+    fn apply(&mut self, record: &Record) {
+        self.plotter.apply(record);
+    }
+
+    fn apply_partial(&mut self, record: &Record, from: f32, to: f32) {
+        self.plotter.apply_partial(record, from, to);
+    }
+
+    fn overlay(&mut self) {
+        self.snapshot = self
+            .context
+            .get_image_data(0.0, 0.0, f64::from(self.size.0), f64::from(self.size.1))
+            .ok();
+        self.plotter.draw_overlay();
+    }
+
+    fn restore(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            let _res = self.context.put_image_data(&snapshot, 0.0, 0.0);
+        }
+    }
+}
+
+/// Plays a program onto a canvas a frame at a time, so the turtle can be
+/// seen moving. Drive it with `step` from `requestAnimationFrame`.
+///
+/// A frame is a tick: records are played at their `time`, so turtles draw
+/// side by side and a turtle's `WAIT` holds up no one else.
+#[wasm_bindgen]
+pub struct Player {
+    surface: Box<dyn Surface>,
+    /// Records by the tick they start at, each turtle's in the order it
+    /// issued them, with how many ticks each takes at its turtle's speed
+    records: Vec<(Record, usize)>,
+    // next record to start, the tick being played, and the records started
+    // but not finished with how many of their pieces were drawn already
+    index: usize,
+    tick: usize,
+    playing: Vec<(usize, usize)>,
+}
+
+#[wasm_bindgen]
+impl Player {
+    /// run `code` and get ready to play it from the start
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the program fails to run.
+    #[wasm_bindgen(constructor)]
+    pub fn new(opts: &Opts, code: &str) -> Result<Player, JsValue> {
+        let (canvas, context) = crate::canvas_context(&opts.canvas_id);
+        let mut exec = Executor::new();
+        exec.set_locale(opts.locale());
+        let mut tt = Turtle::new((opts.x, opts.y), opts.budget);
+        tt.set_speed(opts.speed);
+        tt.canvas = Some((canvas.width() as f32, canvas.height() as f32));
+        exec.run(&mut tt, code)
+            .map_err(|err| JsValue::from(opts.locale().message(&err)))?;
+
+        let size = (canvas.width(), canvas.height());
+        let surface = CanvasSurface {
+            plotter: CanvasPlotter::new(context.clone(), size, opts.zoom),
+            context,
+            size,
+            snapshot: None,
+        };
+        Ok(Self::play(Box::new(surface), tt.commands(), opts.speed))
+    }
+
+    /// draw the next `frames` frames. Each frame, every line and arc being
+    /// played advances by its turtle's speed, waits hold their turtle for
+    /// their ticks, and anything else is drawn as its tick comes.
+    /// Returns `true` while there's more to play.
+    pub fn step(&mut self, frames: usize) -> bool {
+        self.surface.restore();
+        self.start();
+        for _ in 0..frames {
+            if self.is_done() {
+                break;
+            }
+            for (index, piece) in &mut self.playing {
+                let (record, pieces) = &self.records[*index];
+                if let Command::Wait(_) = record.command {
+                    // nothing to draw, just let the frame pass
+                } else if *pieces == 1 {
+                    self.surface.apply(record);
+                } else {
+                    let from = *piece as f32 / *pieces as f32;
+                    let to = (*piece + 1) as f32 / *pieces as f32;
+                    self.surface.apply_partial(record, from, to);
+                }
+                *piece += 1;
+            }
+            let records = &self.records;
+            self.playing
+                .retain(|(index, piece)| *piece < records[*index].1);
+            self.tick += 1;
+            self.start();
+        }
+        self.surface.overlay();
+        !self.is_done()
+    }
+
+    /// redraw from scratch up to (not including) the record at `index`, in
+    /// the order records are played
+    pub fn seek(&mut self, index: usize) {
+        let index = index.min(self.records.len());
+        self.surface.reset();
+        for (record, _) in &self.records[..index] {
+            self.surface.apply(record);
+        }
+        self.index = index;
+        self.tick = self.records.get(index).map_or(0, |(record, _)| record.time);
+        self.playing.clear();
+        self.surface.overlay();
+    }
+
+    /// how much was played so far, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.records.is_empty() {
+            return 1.0;
+        }
+        (self.index - self.playing.len()) as f32 / self.records.len() as f32
+    }
+
+    /// number of records to play, the range for `seek`
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl Player {
+    /// get ready to play `records` on `surface` from the start, with turtles
+    /// at `speed` until they set their own
+    fn play(surface: Box<dyn Surface>, records: Vec<Record>, speed: f32) -> Self {
+        let mut speeds = BTreeMap::new();
+        let mut records: Vec<(Record, usize)> = records
+            .into_iter()
+            .map(|record| {
+                let speed = speeds.entry(record.turtle).or_insert(speed);
+                let ticks = record.command.ticks(*speed);
+                if let Command::Speed(new) = record.command {
+                    *speed = new;
+                }
+                (record, ticks)
+            })
+            .collect();
+        // stable, so each turtle's records stay in order
+        records.sort_by_key(|(record, _)| record.time);
+        let mut player = Self {
+            surface,
+            records,
+            index: 0,
+            tick: 0,
+            playing: Vec::new(),
+        };
+        player.seek(0);
+        player
+    }
+
+    /// Starts the records whose tick has come: ones that take no time are
+    /// drawn whole, the others are played from the next frame on
+    fn start(&mut self) {
+        while let Some((record, pieces)) = self.records.get(self.index) {
+            if record.time > self.tick {
+                break;
+            }
+            if *pieces == 0 {
+                self.surface.apply(record);
+            } else {
+                self.playing.push((self.index, 0));
+            }
+            self.index += 1;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.index == self.records.len() && self.playing.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logo::turtle::DEFAULT_SPEED;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A surface that writes down what it was asked to draw, records by their
+    /// turtle and the kind of command
+    #[derive(Clone, Default)]
+    struct Log(Rc<RefCell<Vec<String>>>);
+
+    impl Log {
+        fn take(&self) -> Vec<String> {
+            self.0.take()
+        }
+    }
+
+    fn kind(command: &Command) -> &'static str {
+        match command {
+            Command::Color(_) => "color",
+            Command::Line(..) => "line",
+            Command::Move(..) => "move",
+            Command::Speed(_) => "speed",
+            Command::Wait(_) => "wait",
+            _ => "other",
+        }
+    }
+
+    impl Surface for Log {
+        fn reset(&mut self) {
+            self.0.borrow_mut().push(String::from("reset"));
+        }
+
+        fn apply(&mut self, record: &Record) {
+            self.0
+                .borrow_mut()
+                .push(format!("{} {}", record.turtle, kind(&record.command)));
+        }
+
+        fn apply_partial(&mut self, record: &Record, from: f32, to: f32) {
+            self.0.borrow_mut().push(format!(
+                "{} {} {from}..{to}",
+                record.turtle,
+                kind(&record.command)
+            ));
+        }
+
+        fn overlay(&mut self) {
+            self.0.borrow_mut().push(String::from("overlay"));
+        }
+
+        fn restore(&mut self) {
+            self.0.borrow_mut().push(String::from("restore"));
+        }
+    }
+
+    /// a color, a line 10 long, a speed of 5 and another line 10 long, timed
+    /// as a turtle at speed 0 times them
+    fn player(log: &Log) -> Player {
+        let records = [
+            (0, Command::Color((255, 0, 0))),
+            (0, Command::Line((0.0, 0.0), (0.0, 10.0))),
+            (1, Command::Speed(5.0)),
+            (1, Command::Line((0.0, 10.0), (10.0, 10.0))),
+        ]
+        .into_iter()
+        .map(|(time, command)| Record {
+            turtle: 0,
+            time,
+            command,
+        })
+        .collect();
+        Player::play(Box::new(log.clone()), records, 0.0)
+    }
+
+    #[test]
+    fn seek_backwards_redraws_from_the_start() {
+        let log = Log::default();
+        let mut player = player(&log);
+        player.seek(4);
+        log.take();
+
+        player.seek(1);
+        assert_eq!(log.take(), ["reset", "0 color", "overlay"]);
+        assert!((player.progress() - 0.25).abs() < f32::EPSILON);
+
+        // the speed set past the seek point doesn't count yet: the first
+        // line takes a single frame
+        assert!(player.step(1));
+        assert_eq!(log.take(), ["restore", "0 line", "0 speed", "overlay"]);
+    }
+
+    #[test]
+    fn seek_forwards_draws_what_comes_before_the_index() {
+        let log = Log::default();
+        let mut player = player(&log);
+        player.seek(1);
+        log.take();
+
+        player.seek(3);
+        assert_eq!(
+            log.take(),
+            ["reset", "0 color", "0 line", "0 speed", "overlay"]
+        );
+        // the line after it is played at the speed seeked past
+        assert!(player.step(1));
+        assert_eq!(log.take(), ["restore", "0 line 0..0.5", "overlay"]);
+
+        player.seek(100);
+        assert!((player.progress() - 1.0).abs() < f32::EPSILON);
+        assert!(!player.step(1));
+    }
+
+    #[test]
+    fn progress_goes_from_nothing_played_to_everything_played() {
+        let log = Log::default();
+        let mut player = player(&log);
+        assert!(player.progress().abs() < f32::EPSILON);
+        while player.step(1) {}
+        assert!((player.progress() - 1.0).abs() < f32::EPSILON);
+
+        let empty = Player::play(Box::new(Log::default()), Vec::new(), 0.0);
+        assert!((empty.progress() - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn turtles_draw_in_the_same_frames() {
+        let mut turtle = Turtle::new((0.0, 0.0), 100);
+        turtle.tell(vec![1, 2]).unwrap();
+        turtle.forward(20.0).unwrap();
+        let log = Log::default();
+        let mut player = Player::play(Box::new(log.clone()), turtle.commands(), DEFAULT_SPEED);
+        log.take();
+
+        assert!(player.step(1));
+        assert_eq!(
+            log.take(),
+            [
+                "restore",
+                "0 move",
+                "1 move",
+                "2 move",
+                "1 line 0..0.5",
+                "2 line 0..0.5",
+                "overlay"
+            ]
+        );
+        assert!(!player.step(1));
+        assert_eq!(
+            log.take(),
+            ["restore", "1 line 0.5..1", "2 line 0.5..1", "overlay"]
+        );
+    }
+
+    #[test]
+    fn a_wait_holds_up_its_own_turtle_alone() {
+        let mut turtle = Turtle::new((0.0, 0.0), 100);
+        turtle.tell(vec![1, 2]).unwrap();
+        turtle.tell(vec![1]).unwrap();
+        turtle.wait(5.0).unwrap();
+        turtle.forward(10.0).unwrap();
+        turtle.tell(vec![2]).unwrap();
+        turtle.forward(10.0).unwrap();
+        let log = Log::default();
+        let mut player = Player::play(Box::new(log.clone()), turtle.commands(), DEFAULT_SPEED);
+        log.take();
+
+        assert!(player.step(1));
+        assert_eq!(
+            log.take(),
+            ["restore", "0 move", "1 move", "2 move", "2 line", "overlay"]
+        );
+        // turtle 1 goes on once its wait is over
+        assert!(player.step(4));
+        assert_eq!(log.take(), ["restore", "overlay"]);
+        assert!(!player.step(1));
+        assert_eq!(log.take(), ["restore", "1 line", "overlay"]);
+    }
+}
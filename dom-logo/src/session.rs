@@ -0,0 +1,186 @@
+use crate::logo::debugger::Resume;
+use crate::logo::executor::Executor;
+use crate::logo::locale::Locale;
+use crate::logo::turtle::{Origin, Record, Turtle};
+use crate::logo::Diagnostic;
+use crate::{to_js, Opts};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// Where a [`Session`] is after a `step`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Status {
+    Running,
+    /// Stopped in the debugger, until told how to go on
+    Paused,
+    Done,
+    /// Cancelled by `stop` before it was done
+    Stopped,
+    Error,
+}
+
+/// what `step` hands back: the status, and the commands emitted since the
+/// previous step, along with their origins when tracing
+#[derive(Serialize)]
+struct Slice {
+    status: Status,
+    commands: Vec<Record>,
+    trace: Option<Vec<Origin>>,
+    error: Option<String>,
+}
+
+/// Runs a program a slice at a time, so the front end can render between
+/// slices instead of freezing until the program is done.
+#[wasm_bindgen]
+pub struct Session {
+    exec: Executor,
+    turtle: Turtle,
+    // commands already handed out by `step`
+    sent: usize,
+    status: Status,
+    error: Option<String>,
+    locale: &'static Locale,
+}
+
+#[wasm_bindgen]
+impl Session {
+    /// compile `code` and get ready to run it, without running anything yet
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the program doesn't parse.
+    #[wasm_bindgen(constructor)]
+    pub fn new(opts: &Opts, code: &str) -> Result<Session, JsValue> {
+        let mut exec = Executor::new();
+        exec.set_locale(opts.locale());
+        exec.start_lint();
+        let mut turtle = Turtle::new((opts.x, opts.y), opts.budget);
+        turtle.set_speed(opts.speed);
+        turtle.canvas = crate::canvas_size(&opts.canvas_id);
+        if opts.trace {
+            turtle.start_trace();
+        }
+        if opts.profile {
+            exec.start_profile();
+        }
+        exec.load(code)
+            .map_err(|err| JsValue::from(opts.locale().message(&err)))?;
+        Ok(Self {
+            exec,
+            turtle,
+            sent: 0,
+            status: Status::Running,
+            error: None,
+            locale: opts.locale(),
+        })
+    }
+
+    /// run up to `max_instructions` instructions, and return the status with
+    /// the commands emitted since the last call. Once the session is done,
+    /// failed or stopped, it stays that way.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the result can't be serialized.
+    pub fn step(&mut self, max_instructions: usize) -> Result<JsValue, JsValue> {
+        if matches!(self.status, Status::Running | Status::Paused) {
+            match self.exec.step(&mut self.turtle, max_instructions) {
+                Ok(true) => self.status = Status::Done,
+                Ok(false) if self.exec.paused() => self.status = Status::Paused,
+                Ok(false) => self.status = Status::Running,
+                Err(err) => {
+                    self.status = Status::Error;
+                    self.error = Some(self.locale.message(&err));
+                }
+            }
+        }
+        let commands = self.turtle.commands[self.sent..].to_vec();
+        let trace = self
+            .turtle
+            .trace
+            .as_ref()
+            .map(|trace| trace[self.sent..].to_vec());
+        self.sent = self.turtle.commands.len();
+        let slice = Slice {
+            status: self.status,
+            commands,
+            trace,
+            error: self.error.clone(),
+        };
+        to_js(&slice)
+    }
+
+    /// cancel the program; following steps report `Stopped` with no commands
+    pub fn stop(&mut self) {
+        if matches!(self.status, Status::Running | Status::Paused) {
+            self.status = Status::Stopped;
+        }
+    }
+
+    /// lines written by PRINT, SHOW and TYPE so far
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lines can't be serialized.
+    pub fn output(&self) -> Result<JsValue, JsValue> {
+        to_js(&self.exec.output())
+    }
+
+    /// warnings about the code, as `run` reports them
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the warnings can't be serialized.
+    pub fn warnings(&self) -> Result<JsValue, JsValue> {
+        let warnings: Vec<Diagnostic> = self.exec.warnings();
+        to_js(&warnings)
+    }
+
+    /// debug the program, pausing before statements on these source lines
+    /// (1-based). An empty list keeps debugging, for stepping alone.
+    pub fn set_breakpoints(&mut self, lines: Vec<u32>) {
+        let lines: Vec<usize> = lines.into_iter().map(|line| line as usize).collect();
+        self.exec.set_breakpoints(&lines);
+    }
+
+    /// go on until the next breakpoint
+    pub fn resume(&mut self) {
+        self.exec.resume(Resume::Continue);
+    }
+
+    /// go on to the next statement, stepping into procedure calls
+    pub fn step_into(&mut self) {
+        self.exec.resume(Resume::StepInto);
+    }
+
+    /// go on to the next statement, running procedure calls through
+    pub fn step_over(&mut self) {
+        self.exec.resume(Resume::StepOver);
+    }
+
+    /// go on until the running procedure returns
+    pub fn step_out(&mut self) {
+        self.exec.resume(Resume::StepOut);
+    }
+
+    /// the call stack with each frame's inputs and statement, the active
+    /// turtle's state and the last command, for the editor to show where the
+    /// program is
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the snapshot can't be serialized.
+    pub fn snapshot(&self) -> Result<JsValue, JsValue> {
+        to_js(&self.exec.snapshot(&self.turtle))
+    }
+
+    /// calls, instructions and commands by procedure and source line so far,
+    /// or `null` unless `opts.profile` was set
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the profile can't be serialized.
+    pub fn profile(&self) -> Result<JsValue, JsValue> {
+        to_js(&self.exec.profile())
+    }
+}
@@ -16,11 +16,17 @@ const redraw = (code, canvas, theme, onDidDraw) => {
   opts.canvas_id = 'plot'
   opts.x = canvas.width / 2
   opts.y = canvas.height / 2
-  opts.budget = 250 * 1000 // 250k instructions are roughly 300ms on mac m1
+  // the budget counts every VM instruction run, not just the commands drawn,
+  // so it is higher than the 250k commands it used to be: a million is what
+  // the fern example needs, and still stops a loop that never ends
+  opts.budget = 1000 * 1000
+  // keywords and error messages follow the toolbar's language pack
+  opts.locale = useStore.getState().langpack
   // console.log(opts)
   let error = null
+  let warnings = []
   try {
-    draw(opts, transpile(code || ''))
+    warnings = draw(opts, transpile(code || '')).warnings
     /*
     const commands = run(opts, transpile(code || ''))
     const p = new JsCanvasPlotter(context, {
@@ -30,11 +36,13 @@ const redraw = (code, canvas, theme, onDidDraw) => {
     p.plot(commands)
     */
   } catch (ex) {
-    error = ex.toString()
+    // failed runs throw {message, warnings}, the warnings often explaining why
+    error = ex.message ?? ex.toString()
+    warnings = ex.warnings ?? []
     console.log('err', error)
   }
 
-  const res = { error, time: window.performance.now() - start }
+  const res = { error, warnings, time: window.performance.now() - start }
   if (onDidDraw) {
     onDidDraw(res)
   } else {
@@ -51,6 +59,7 @@ const Canvas = () => {
   const layout = useStore((s) => s.layout)
   const code = useStore((s) => s.localcode)
   const onDidDraw = useStore((s) => s.setResult)
+  const langpack = useStore((s) => s.langpack)
 
   useLayoutEffect(() => {
     const debouncedRedraw = debounce(redraw, 150)
@@ -85,7 +94,7 @@ const Canvas = () => {
 
   useEffect(() => {
     redraw(code, canvasRef.current, theme, onDidDraw)
-  }, [code, theme]) // eslint-disable-line react-hooks/exhaustive-deps
+  }, [code, theme, langpack]) // eslint-disable-line react-hooks/exhaustive-deps
 
   return (
     <Box ref={parentContainerRef} w="100%" h="100%">
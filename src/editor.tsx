@@ -50,6 +50,8 @@ const Editor = () => {
         title={drawResult?.error && drawResult.error}
       >
         {drawResult?.error && `⛔️ error: ${drawResult?.error}`}{' '}
+        {drawResult?.warnings?.[0] &&
+          `⚠️ line ${drawResult.warnings[0].span.line}: ${drawResult.warnings[0].message}`}{' '}
         {drawResult?.time != null && `in ${drawResult?.time.toFixed(2)}ms`}
       </Box>
     </>
@@ -36,9 +36,9 @@ export class JsCanvasPlotter {
     this.canvas.fill()
   }
 
-  plot(commands: any[]) {
+  plot(records: any[]) {
     this.clear()
-    for (const command of commands) {
+    for (const { command } of records) {
       if (command.Move) {
         this.currentPos = { x: command.Move[0], y: command.Move[1] }
       } else if (command.Line) {
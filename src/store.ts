@@ -63,9 +63,14 @@ const localStoragePersistConfig = {
   name: 'logo-store',
   partialize: filterStateForStorage,
 }
+export interface Diagnostic {
+  message: string
+  span: { line: number; column: number; start: number; end: number }
+}
 export interface DrawResult {
   time?: number
   error?: any
+  warnings?: Diagnostic[]
 }
 interface AppState {
   direction: string